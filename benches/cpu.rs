@@ -0,0 +1,47 @@
+#![feature(test)]
+extern crate test;
+extern crate gamecrab;
+
+use gamecrab::cartridge::Cartridge;
+use gamecrab::gameboy::GameBoy;
+use test::Bencher;
+
+// A tight loop of loads, alu and cb ops, memory accesses, calls and branches
+const BUSY_LOOP: [(usize, &[u8]); 2] = [
+    (0x0100, &[
+        0x21, 0x00, 0xC0, // LD HL,0xC000
+        0x06, 0x40,       // LD B,0x40
+        0x7E,             // LD A,(HL)
+        0x80,             // ADD A,B
+        0x22,             // LD (HL+),A
+        0xA9,             // XOR C
+        0xCB, 0x37,       // SWAP A
+        0xCB, 0x7E,       // BIT 7,(HL)
+        0xCD, 0x20, 0x01, // CALL 0x0120
+        0x05,             // DEC B
+        0x20, 0xF2,       // JR NZ,0x0105
+        0x18, 0xEB,       // JR 0x0100
+    ]),
+    (0x0120, &[
+        0x0C, // INC C
+        0xC9, // RET
+    ]),
+];
+
+fn busy_gameboy() -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    for &(address, code) in BUSY_LOOP.iter() {
+        rom[address..address + code.len()].copy_from_slice(code);
+    }
+    let mut gb = GameBoy::new();
+    gb.skip_boot_rom();
+    gb.cartridge = Cartridge::new(rom).unwrap();
+    gb.cart_loaded = true;
+    gb
+}
+
+#[bench]
+fn bench_run_frame(b: &mut Bencher) {
+    let mut gb = busy_gameboy();
+    b.iter(|| gb.run_frame());
+}
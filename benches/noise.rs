@@ -6,7 +6,6 @@ macro_rules! cond {
 }
 extern crate test;
 extern crate gamecrab;
-use gamecrab::cpu::*;
 
 // pub fn mix_channel_4(result: &mut Vec<i16>) {
 // let nr43 = 0xBF;//cpu.memory[0xFF22];
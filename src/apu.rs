@@ -1,6 +1,6 @@
-extern crate sdl2;
-use self::sdl2::audio::AudioSpecDesired;
-use cpu::*;
+// A second of audio at 44100hz
+pub const MAX_QUEUED_SAMPLES: usize = 44100;
+
 
 pub struct WaveChannel {
     pub counter: u16,
@@ -51,10 +51,11 @@ pub struct Apu {
     pub channel_2: SquareChannel,
     pub channel_3: WaveChannel,
     pub channel_4: NoiseChannel,
-    pub audio_queue: sdl2::audio::AudioQueue<i16>,
-    pub prev_size: i32,
+    // Mixed samples waiting for the host to play them
     pub audio_vec_queue: Vec<i16>,
     pub audio_freq: u32,
+    // NR10-NR52 and wave ram, 0xFF10-0xFF3F
+    pub regs: [u8; 0x30],
 }
 
 impl Default for NoiseChannel {
@@ -110,7 +111,6 @@ impl Default for Apu {
             sweep_negate: false,
             envelope_clock: 0,
             sample_length_arr: sample_len_arr(),
-            audio_queue: init_audio(44100),
             sweeping: false,
             channel_1_shadow_freq: 0,
             channel_1_pos: 0,
@@ -123,12 +123,68 @@ impl Default for Apu {
             channel_4: Default::default(),
             audio_freq: audio_freq,
             audio_vec_queue: Vec::new(),
-            prev_size: 0,
+            regs: [0; 0x30],
         }
     }
 }
 
 impl Apu {
+    pub fn read(&self, address: usize) -> u8 {
+        self.regs[address - 0xFF10]
+    }
+
+    pub fn write(&mut self, address: usize, val: u8) {
+        match address {
+            0xFF11 => {
+                self.channel_1.counter = 64 - (val & 0x3F) as u16;
+                self.set_reg(address, val);
+            } //NR 11 Sound Mode 1 Duty/Sound length
+            0xFF12 => {
+                let (vol_init, vol_add, vol_period) = (val >> 4, val & 8 != 0, val & 7);
+                self.channel_1.volume = vol_init;
+                self.channel_1.incr_vol = vol_add;
+                self.channel_1.envelope_period = vol_period;
+                self.set_reg(address, val)
+            } //NR 12 Sound Mode 1 Envelope
+            0xFF14 => write_nx4_address(address, val, self), //NR 14 Sound Mode 1 Frequency hi
+            0xFF16 => {
+                self.channel_2.counter = 64 - (val & 0x3F) as u16;
+                self.set_reg(address, val);
+            } //NR 21 Sound Mode 2 Duty/Sound length
+            0xFF17 => {
+                let (vol_init, vol_add, vol_period) = (val >> 4, val & 8 != 0, val & 7);
+                self.channel_2.volume = vol_init;
+                self.channel_2.incr_vol = vol_add;
+                self.channel_2.envelope_period = vol_period;
+                self.set_reg(address, val)
+            } //NR 22 Sound Mode 2 Envelope
+            0xFF19 => write_nx4_address(address, val, self), //NR 24 Sound Mode 2 Frequency hi
+            0xFF1B => {
+                self.channel_3.counter = 256 - val as u16;
+                self.set_reg(address, val);
+            } //NR 31 Sound Mode 3 Sound length
+            0xFF1E => write_nx4_address(address, val, self), //NR 34 Sound Mode 3 Frequency hi
+            0xFF20 => {
+                self.channel_4.counter = 64 - (val & 0x3F) as u16;
+                self.set_reg(address, val);
+            } //NR 41 Sound Mode 4 Sound length
+            0xFF21 => {
+                let (vol_init, vol_add, vol_period) = (val >> 4, val & 8 != 0, val & 7);
+                self.channel_4.volume = vol_init;
+                self.channel_4.incr_vol = vol_add;
+                self.channel_4.envelope_period = vol_period;
+                self.set_reg(address, val);
+            } //NR 42 Sound Mode 4 Envelope
+            0xFF23 => write_nx4_address(address, val, self), //NR 44 Sound Mode 4 Counter
+            _ => self.set_reg(address, val),
+        }
+    }
+
+    // Stores a register without any of the side effects of writing it
+    pub fn set_reg(&mut self, address: usize, val: u8) {
+        self.regs[address - 0xFF10] = val;
+    }
+
     pub fn freq_sweep(&self, shift: u8, negate: bool) -> u32 {
         let freq = self.channel_1_shadow_freq;
         let delta = freq >> shift;
@@ -136,87 +192,197 @@ impl Apu {
     }
 }
 
-pub fn gen_samples(sample_len: u8, cpu: &mut Cpu) {
+pub fn gen_samples(sample_len: u8, apu: &mut Apu) {
     let mut result = vec![0; sample_len as usize];
-    let nr51 = cpu.memory[0xFF25];
-    if cpu.apu.channel_1.enabled {
-        let registers = read_channel_1_addresses(cpu);
-        let mut channel = &mut cpu.apu.channel_1;
+    let nr51 = apu.read(0xFF25);
+    if apu.channel_1.enabled {
+        let registers = read_channel_1_addresses(apu);
+        let mut channel = &mut apu.channel_1;
         let sound = ((nr51 >> 4) & 1, nr51 & 1);
         mix_channel_square(&mut result, channel, registers, sound);
     }
-    if cpu.apu.channel_2.enabled {
-        let registers = read_channel_2_addresses(cpu);
-        let mut channel = &mut cpu.apu.channel_2;
+    if apu.channel_2.enabled {
+        let registers = read_channel_2_addresses(apu);
+        let mut channel = &mut apu.channel_2;
         let sound = ((nr51 >> 5) & 1, (nr51 >> 1) & 1);
         mix_channel_square(&mut result, channel, registers, sound);
     }
-    if cpu.apu.channel_3.enabled {
-        let channel_3_registers = read_channel_3_addresses(cpu);
-        let wave_table = &cpu.memory[0xFF30..(0xFF30 + 16)];
-        let mut channel_3 = &mut cpu.apu.channel_3;
+    if apu.channel_3.enabled {
+        let channel_3_registers = read_channel_3_addresses(apu);
+        let mut wave_table = [0; 16];
+        for (i, sample) in wave_table.iter_mut().enumerate() {
+            *sample = apu.read(0xFF30 + i);
+        }
+        let mut channel_3 = &mut apu.channel_3;
         let sound = ((nr51 >> 6) & 1, (nr51 >> 2) & 1);
         mix_channel_3(
             &mut result,
             &mut channel_3,
-            wave_table,
+            &wave_table,
             channel_3_registers,
             sound,
         );
     }
-    if cpu.apu.channel_4.enabled {
-        let channel_4_registers = cpu.memory[0xFF22];
-        let mut channel_4 = &mut cpu.apu.channel_4;
+    if apu.channel_4.enabled {
+        let channel_4_registers = apu.read(0xFF22);
+        let mut channel_4 = &mut apu.channel_4;
         mix_channel_4(&mut result, &mut channel_4, channel_4_registers, nr51);
     }
-    cpu.apu.audio_vec_queue.append(&mut result);
+    apu.audio_vec_queue.append(&mut result);
+    // Nobody may be draining the samples when running headless, only the
+    // latest ones are kept
+    let queued = apu.audio_vec_queue.len();
+    if queued > MAX_QUEUED_SAMPLES {
+        apu.audio_vec_queue.drain(..queued - MAX_QUEUED_SAMPLES);
+    }
 }
 
-pub fn queue(cpu: &mut Cpu) {
-    if cpu.apu.audio_queue.size() == 0 {
-        //    println!("Empty queue");
+fn write_nx4_address(address: usize, val: u8, apu: &mut Apu) -> () {
+    // Check if should trigger
+    if (val & 0x80) != 0 {
+        match address {
+            0xFF14 => {
+                let nr10 = apu.read(0xFF10);
+                let nr12 = apu.read(0xFF12);
+                let nr13 = apu.read(0xFF13);
+                let nr14 = apu.read(0xFF14);
+                let time_freq = (nr14 as u32 & 7) << 8 | nr13 as u32;
+                let not_time_freq = 4 * (2048 - time_freq as u32);
+                apu.channel_1.enabled = true;
+                if apu.channel_1.counter == 0 {
+                    apu.channel_1.counter = 64;
+                }
+                apu.channel_1.envelope_pos = nr12 & 7;
+                apu.channel_1.freq_pos = not_time_freq;
+                apu.channel_1_shadow_freq = not_time_freq;
+                apu.sweep_period = (nr10 >> 4) & 7;
+                apu.sweep_clock = apu.sweep_period;
+                let shift = nr10 & 7;
+                apu.sweeping = apu.sweep_clock != 0 || shift != 0;
+                apu.sweep_negate = (nr10 & 8) != 0;
+                apu.channel_1.volume = (nr12 & 0xF0) >> 4;
+                apu.channel_1.enabled = nr12 & 0xF8 != 0;
+                if apu.sweeping && apu.freq_sweep(shift, apu.sweep_negate) > 2047 {
+                    apu.channel_1.enabled = false;
+                }
+            }
+            0xFF19 => {
+                let nr22 = apu.read(0xFF17);
+                let nr23 = apu.read(0xFF18);
+                let nr24 = apu.read(0xFF19);
+                let time_freq = (nr24 as u32 & 0b111) << 8 | nr23 as u32;
+                let not_time_freq = 4 * (2048 - time_freq as u32);
+                apu.channel_2.enabled = true;
+                if apu.channel_2.counter == 0 {
+                    apu.channel_2.counter = 64;
+                }
+                apu.channel_2.envelope_pos = nr22 & 7;
+                apu.channel_2.freq_pos = not_time_freq;
+                apu.channel_2.volume = (nr22 & 0xF0) >> 4;
+                apu.channel_2.enabled = nr22 & 0xF8 != 0;
+            }
+            0xFF1E => {
+                apu.channel_3.enabled = true;
+                if apu.channel_3.counter == 0 {
+                    apu.channel_3.counter = 256;
+                }
+                apu.channel_3_wave_pos = 0;
+                let nr32 = apu.read(0xFF1C);
+                let nr33 = apu.read(0xFF1D) as u32;
+                let nr34 = apu.read(0xFF1E) as u32;
+                let time_freq = (nr34 & 7) << 8 | nr33;
+                apu.channel_3_pos = 2 * (2048 - time_freq);
+                apu.channel_3.volume = (nr32 & 0x60) >> 5;
+                apu.channel_3.enabled = (apu.read(0xFF1A) & 0x80) != 0;
+            }
+            0xFF23 => {
+                let nr42 = apu.read(0xFF21);
+                let nr43 = apu.read(0xFF22);
+                let divisors = [8, 16, 32, 48, 64, 80, 96, 112];
+                let dividing_ratio = divisors[(nr43 & 0x7) as usize];
+                let shift_clock_freq = nr43 >> 4 as u32;
+                let timer_freq = dividing_ratio << shift_clock_freq;
+                apu.channel_4.enabled = true;
+                if apu.channel_4.counter == 0 {
+                    apu.channel_4.counter = 64;
+                }
+                apu.channel_4.lfsr = 0x7FFF;
+                apu.channel_4.freq_pos = timer_freq;
+                apu.channel_4.envelope_pos = nr42 & 7;
+                apu.channel_4.volume = nr42 >> 4;
+                apu.channel_4.enabled = nr42 & 0xF8 != 0;
+                // println!("Wrote {:4>0X} to NR44", val);
+            }
+            _ => {}
+        };
     }
+    apu.set_reg(address, val);
+}
+
+pub fn read_channel_1_addresses(apu: &Apu) -> (u8, u8, u8) {
+    (apu.read(0xFF11), apu.read(0xFF13), apu.read(0xFF14))
+}
+
+pub fn read_channel_2_addresses(apu: &Apu) -> (u8, u8, u8) {
+    (apu.read(0xFF16), apu.read(0xFF18), apu.read(0xFF19))
+}
 
-    // This is the maximum the queue can be delayed by
-    cpu.apu.audio_queue.queue(&cpu.apu.audio_vec_queue);
+pub fn read_channel_3_addresses(apu: &Apu) -> (u8, u8, u8, u8) {
+    (
+        apu.read(0xFF1A),
+        apu.read(0xFF1C),
+        apu.read(0xFF1D),
+        apu.read(0xFF1E),
+    )
+}
+
+pub fn read_channel_4_addresses(apu: &Apu) -> (u8, u8, u8, u8) {
+    (
+        apu.read(0xFF20),
+        apu.read(0xFF21),
+        apu.read(0xFF22),
+        apu.read(0xFF23),
+    )
+}
 
-    cpu.apu.audio_vec_queue.clear();
-    cpu.apu.prev_size = cpu.apu.audio_queue.size() as i32;
+// Hands the samples mixed since the last call over to the host
+pub fn take_samples(apu: &mut Apu) -> Vec<i16> {
+    std::mem::replace(&mut apu.audio_vec_queue, Vec::new())
 }
 
-pub fn step_length(cpu: &mut Cpu) {
-    let channel_1_length_enable = read_address(0xFF14, cpu) & 0x40 != 0;
-    let channel_2_length_enable = read_address(0xFF19, cpu) & 0x40 != 0;
-    let channel_3_length_enable = read_address(0xFF1E, cpu) & 0x40 != 0;
-    let channel_4_length_enable = read_address(0xFF23, cpu) & 0x40 != 0;
-    if cpu.apu.channel_1.enabled && channel_1_length_enable {
-        if cpu.apu.channel_1.counter == 0 {
-            cpu.apu.channel_1.enabled = false;
+pub fn step_length(apu: &mut Apu) {
+    let channel_1_length_enable = apu.read(0xFF14) & 0x40 != 0;
+    let channel_2_length_enable = apu.read(0xFF19) & 0x40 != 0;
+    let channel_3_length_enable = apu.read(0xFF1E) & 0x40 != 0;
+    let channel_4_length_enable = apu.read(0xFF23) & 0x40 != 0;
+    if apu.channel_1.enabled && channel_1_length_enable {
+        if apu.channel_1.counter == 0 {
+            apu.channel_1.enabled = false;
         } else {
-            cpu.apu.channel_1.counter -= 1;
+            apu.channel_1.counter -= 1;
         }
     }
-    if cpu.apu.channel_2.enabled && channel_2_length_enable {
-        if cpu.apu.channel_2.counter == 0 {
-            cpu.apu.channel_2.enabled = false;
+    if apu.channel_2.enabled && channel_2_length_enable {
+        if apu.channel_2.counter == 0 {
+            apu.channel_2.enabled = false;
         } else {
-            cpu.apu.channel_2.counter -= 1;
+            apu.channel_2.counter -= 1;
         }
     }
 
-    if cpu.apu.channel_3.enabled && channel_3_length_enable {
-        if cpu.apu.channel_3.counter == 0 {
-            cpu.apu.channel_3.enabled = false;
+    if apu.channel_3.enabled && channel_3_length_enable {
+        if apu.channel_3.counter == 0 {
+            apu.channel_3.enabled = false;
         } else {
-            cpu.apu.channel_3.counter -= 1;
+            apu.channel_3.counter -= 1;
         }
     }
 
-    if cpu.apu.channel_4.enabled && channel_4_length_enable {
-        if cpu.apu.channel_4.counter == 0 {
-            cpu.apu.channel_4.enabled = false;
+    if apu.channel_4.enabled && channel_4_length_enable {
+        if apu.channel_4.counter == 0 {
+            apu.channel_4.enabled = false;
         } else {
-            cpu.apu.channel_4.counter -= 1;
+            apu.channel_4.counter -= 1;
         }
     }
 }
@@ -376,99 +542,99 @@ pub fn mix_channel_4(result: &mut Vec<i16>, channel: &mut NoiseChannel, nr43: u8
     }
 }
 
-pub fn step_sweep(cpu: &mut Cpu) {
-    let nr10 = read_address(0xFF10, cpu);
+pub fn step_sweep(apu: &mut Apu) {
+    let nr10 = apu.read(0xFF10);
     let sweep_period = (nr10 >> 4) & 7;
 
-    if cpu.apu.sweeping && sweep_period != 0 {
-        if cpu.apu.sweep_clock == 0 {
+    if apu.sweeping && sweep_period != 0 {
+        if apu.sweep_clock == 0 {
             let (shift, negate) = (nr10 & 7, nr10 & 8 != 0);
-            let sweep = cpu.apu.freq_sweep(shift, negate);
-            cpu.apu.sweep_clock = sweep_period;
+            let sweep = apu.freq_sweep(shift, negate);
+            apu.sweep_clock = sweep_period;
             if sweep <= 2047 {
-                cpu.apu.channel_1_shadow_freq = sweep;
-                let nr14 = read_address(0xFF14, cpu);
-                write_address(0xFF13, (sweep & 0xFF) as u8, cpu);
-                write_address(0xFF14, (nr14 & 0xF0) | (sweep >> 8) as u8, cpu);
+                apu.channel_1_shadow_freq = sweep;
+                let nr14 = apu.read(0xFF14);
+                apu.set_reg(0xFF13, (sweep & 0xFF) as u8);
+                apu.set_reg(0xFF14, (nr14 & 0xF0) | (sweep >> 8) as u8);
                 // check again
-                let sweep_check = cpu.apu.freq_sweep(shift, negate);
+                let sweep_check = apu.freq_sweep(shift, negate);
                 if sweep_check > 2047 {
-                    cpu.apu.channel_1.enabled = false;
+                    apu.channel_1.enabled = false;
                 }
             }
         } else {
-            cpu.apu.sweep_clock -= 1;
+            apu.sweep_clock -= 1;
         }
     }
 }
 
-pub fn step_envelope(cpu: &mut Cpu) {
-    if cpu.apu.channel_1.enabled && cpu.apu.channel_1.envelope_period != 0 {
-        if cpu.apu.channel_1.incr_vol && cpu.apu.channel_1.volume < 15 {
-            if cpu.apu.channel_1.envelope_pos == 0 {
-                cpu.apu.channel_1.volume += 1;
-                cpu.apu.channel_1.envelope_pos = cpu.apu.channel_1.envelope_period;
+pub fn step_envelope(apu: &mut Apu) {
+    if apu.channel_1.enabled && apu.channel_1.envelope_period != 0 {
+        if apu.channel_1.incr_vol && apu.channel_1.volume < 15 {
+            if apu.channel_1.envelope_pos == 0 {
+                apu.channel_1.volume += 1;
+                apu.channel_1.envelope_pos = apu.channel_1.envelope_period;
             } else {
-                cpu.apu.channel_1.envelope_pos -= 1;
+                apu.channel_1.envelope_pos -= 1;
             }
-        } else if !cpu.apu.channel_1.incr_vol && cpu.apu.channel_1.volume > 0 {
-            if cpu.apu.channel_1.envelope_pos == 0 {
-                cpu.apu.channel_1.volume -= 1;
-                cpu.apu.channel_1.envelope_pos = cpu.apu.channel_1.envelope_period;
+        } else if !apu.channel_1.incr_vol && apu.channel_1.volume > 0 {
+            if apu.channel_1.envelope_pos == 0 {
+                apu.channel_1.volume -= 1;
+                apu.channel_1.envelope_pos = apu.channel_1.envelope_period;
             } else {
-                cpu.apu.channel_1.envelope_pos -= 1;
+                apu.channel_1.envelope_pos -= 1;
             }
         }
     }
-    if cpu.apu.channel_2.enabled && cpu.apu.channel_2.envelope_period != 0 {
-        if cpu.apu.channel_2.incr_vol && cpu.apu.channel_2.volume < 15 {
-            if cpu.apu.channel_2.envelope_pos == 0 {
-                cpu.apu.channel_2.volume += 1;
-                cpu.apu.channel_2.envelope_pos = cpu.apu.channel_2.envelope_period;
+    if apu.channel_2.enabled && apu.channel_2.envelope_period != 0 {
+        if apu.channel_2.incr_vol && apu.channel_2.volume < 15 {
+            if apu.channel_2.envelope_pos == 0 {
+                apu.channel_2.volume += 1;
+                apu.channel_2.envelope_pos = apu.channel_2.envelope_period;
             } else {
-                cpu.apu.channel_2.envelope_pos -= 1;
+                apu.channel_2.envelope_pos -= 1;
             }
-        } else if !cpu.apu.channel_2.incr_vol && cpu.apu.channel_2.volume > 0 {
-            if cpu.apu.channel_2.envelope_pos == 0 {
-                cpu.apu.channel_2.volume -= 1;
-                cpu.apu.channel_2.envelope_pos = cpu.apu.channel_2.envelope_period;
+        } else if !apu.channel_2.incr_vol && apu.channel_2.volume > 0 {
+            if apu.channel_2.envelope_pos == 0 {
+                apu.channel_2.volume -= 1;
+                apu.channel_2.envelope_pos = apu.channel_2.envelope_period;
             } else {
-                cpu.apu.channel_2.envelope_pos -= 1;
+                apu.channel_2.envelope_pos -= 1;
             }
         }
     }
-    if cpu.apu.channel_4.enabled && cpu.apu.channel_4.envelope_period != 0 {
-        if cpu.apu.channel_4.incr_vol && cpu.apu.channel_4.volume < 15 {
-            if cpu.apu.channel_4.envelope_pos == 0 {
-                cpu.apu.channel_4.volume += 1;
-                cpu.apu.channel_4.envelope_pos = cpu.apu.channel_4.envelope_period;
+    if apu.channel_4.enabled && apu.channel_4.envelope_period != 0 {
+        if apu.channel_4.incr_vol && apu.channel_4.volume < 15 {
+            if apu.channel_4.envelope_pos == 0 {
+                apu.channel_4.volume += 1;
+                apu.channel_4.envelope_pos = apu.channel_4.envelope_period;
             } else {
-                cpu.apu.channel_4.envelope_pos -= 1;
+                apu.channel_4.envelope_pos -= 1;
             }
-        } else if !cpu.apu.channel_4.incr_vol && cpu.apu.channel_4.volume > 0 {
-            if cpu.apu.channel_4.envelope_pos == 0 {
-                cpu.apu.channel_4.volume -= 1;
-                cpu.apu.channel_4.envelope_pos = cpu.apu.channel_4.envelope_period;
+        } else if !apu.channel_4.incr_vol && apu.channel_4.volume > 0 {
+            if apu.channel_4.envelope_pos == 0 {
+                apu.channel_4.volume -= 1;
+                apu.channel_4.envelope_pos = apu.channel_4.envelope_period;
             } else {
-                cpu.apu.channel_4.envelope_pos -= 1;
+                apu.channel_4.envelope_pos -= 1;
             }
         }
     }
 }
 
-pub fn step(cpu: &mut Cpu) {
-    cpu.apu.master_clock = (cpu.apu.master_clock + 1) % 512;
-    let sample_len = cpu.apu.sample_length_arr[cpu.apu.master_clock as usize];
-    if cpu.apu.master_clock % 2 == 0 {
-        step_length(cpu);
+pub fn step(apu: &mut Apu) {
+    apu.master_clock = (apu.master_clock + 1) % 512;
+    let sample_len = apu.sample_length_arr[apu.master_clock as usize];
+    if apu.master_clock % 2 == 0 {
+        step_length(apu);
     }
-    if cpu.apu.master_clock % 4 == 0 {
-        step_sweep(cpu);
+    if apu.master_clock % 4 == 0 {
+        step_sweep(apu);
     }
-    if cpu.apu.master_clock % 8 == 7 {
-        step_envelope(cpu);
+    if apu.master_clock % 8 == 7 {
+        step_envelope(apu);
     }
-    gen_samples(sample_len, cpu);
+    gen_samples(sample_len, apu);
 }
 
 fn get_duty_table() -> [u8; 32] {
@@ -478,25 +644,6 @@ fn get_duty_table() -> [u8; 32] {
     ]
 }
 
-pub fn init_audio(freq: i32) -> sdl2::audio::AudioQueue<i16> {
-    let sdl_context = sdl2::init().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
-
-    let desired_spec = AudioSpecDesired {
-        freq: Some(freq),
-        channels: Some(1),
-        // mono  -
-        samples: None, /* default sample size
-                        *        samples: Some(32768), // default sample size */
-    };
-
-    let device = audio_subsystem
-        .open_queue::<i16>(None, &desired_spec)
-        .unwrap();
-    device.resume();
-    device
-}
-
 pub fn sample_len_arr() -> [u8; 512] {
     let mut arr = [0; 512];
     for i in 0..512 {
@@ -0,0 +1,85 @@
+pub trait Bus {
+    fn read(&mut self, address: usize) -> u8;
+    fn write(&mut self, address: usize, val: u8);
+
+    fn read_i8(&mut self, address: usize) -> i8 {
+        self.read(address) as i8
+    }
+}
+
+// A handler for one region of the memory map. Addresses are passed in
+// unchanged so a handler always sees the same addresses the cpu does.
+pub trait Region {
+    fn read(&mut self, address: usize) -> u8;
+    fn write(&mut self, address: usize, val: u8);
+}
+
+pub struct Ram {
+    start: usize,
+    data: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new(start: usize, size: usize) -> Ram {
+        Ram {
+            start: start,
+            data: vec![0; size],
+        }
+    }
+}
+
+impl Region for Ram {
+    fn read(&mut self, address: usize) -> u8 {
+        self.data[address - self.start]
+    }
+
+    fn write(&mut self, address: usize, val: u8) {
+        self.data[address - self.start] = val;
+    }
+}
+
+// The memory no component owns: the boot rom, work ram, high ram and the io
+// registers nothing implements. Accesses are raw, the GameBoy routes
+// everything else to its components before it gets here.
+pub struct MemoryBus {
+    pub boot_rom: Vec<u8>,
+    pub has_booted: bool,
+    pub wram: Box<dyn Region>,
+    pub io: Box<dyn Region>,
+    pub hram: Box<dyn Region>,
+}
+
+impl Default for MemoryBus {
+    fn default() -> MemoryBus {
+        MemoryBus {
+            boot_rom: Vec::new(),
+            has_booted: false,
+            wram: Box::new(Ram::new(0xC000, 0x2000)),
+            io: Box::new(Ram::new(0xFF00, 0x80)),
+            hram: Box::new(Ram::new(0xFF80, 0x80)),
+        }
+    }
+}
+
+impl Bus for MemoryBus {
+    fn read(&mut self, address: usize) -> u8 {
+        match address {
+            0x0000...0x00FF => self.boot_rom.get(address).cloned().unwrap_or(0xFF),
+            0xC000...0xDFFF => self.wram.read(address),
+            0xE000...0xFDFF => self.wram.read(address - 0x2000), // Echo of work ram
+            0xFF00...0xFF7F => self.io.read(address),
+            0xFF80...0xFFFF => self.hram.read(address),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: usize, val: u8) {
+        match address {
+            0xC000...0xDFFF => self.wram.write(address, val),
+            0xE000...0xFDFF => self.wram.write(address - 0x2000, val),
+            0xFF00...0xFF7F => self.io.write(address, val),
+            0xFF80...0xFFFF => self.hram.write(address, val),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use bus::Region;
+use header::*;
+use mbc::*;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum LoadError {
+    MissingFile(PathBuf),
+    Io(PathBuf, io::Error),
+    TooSmall(usize),
+    BadHeader { expected: u8, found: u8 },
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::LoadError::*;
+        match *self {
+            MissingFile(ref path) => write!(f, "{} does not exist", path.display()),
+            Io(ref path, ref e) => write!(f, "failed to read {}: {}", path.display(), e),
+            TooSmall(len) => write!(f, "file is too small ({} bytes)", len),
+            BadHeader { expected, found } => write!(
+                f,
+                "header checksum is 0x{:02X}, the header adds up to 0x{:02X}",
+                expected, found
+            ),
+            UnsupportedMapper(t) => write!(f, "unsupported cartridge type 0x{:02X}", t),
+        }
+    }
+}
+
+// Reads a whole file, telling a missing file apart from other io errors
+pub fn read_file(path: &Path) -> Result<Vec<u8>, LoadError> {
+    fs::read(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => LoadError::MissingFile(path.to_path_buf()),
+        _ => LoadError::Io(path.to_path_buf(), e),
+    })
+}
+
+// The cartridge slot: rom at 0x0000-0x7FFF, external ram at 0xA000-0xBFFF,
+// and writes to the rom area go to the memory bank controller.
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub battery: bool,
+    pub mbc: Box<dyn Mbc>,
+    pub info: CartridgeInfo,
+    // Where battery backed ram is kept, `<rom>.sav` next to the rom
+    pub save_path: Option<PathBuf>,
+    ram_dirty: bool,
+}
+
+impl Default for Cartridge {
+    fn default() -> Cartridge {
+        Cartridge {
+            rom: Vec::new(),
+            ram: Vec::new(),
+            battery: false,
+            mbc: Box::new(RomOnly),
+            info: Default::default(),
+            save_path: None,
+            ram_dirty: false,
+        }
+    }
+}
+
+impl Cartridge {
+    // Parses the header and picks the memory bank controller from the
+    // cartridge type at 0x147
+    pub fn new(rom: Vec<u8>) -> Result<Cartridge, LoadError> {
+        let info = CartridgeInfo::parse(&rom)?;
+        let mbc: Box<dyn Mbc> = match info.mapper {
+            Mapper::RomOnly => Box::new(RomOnly),
+            Mapper::Mbc1 => Box::new(Mbc1::default()),
+            Mapper::Mbc2 => Box::new(Mbc2::default()),
+            Mapper::Mbc3 => Box::new(Mbc3::new(info.timer)),
+            Mapper::Mbc5 => Box::new(Mbc5::new(info.rumble)),
+        };
+        Ok(Cartridge {
+            rom: rom,
+            ram: vec![0; info.ram_size],
+            battery: info.battery,
+            mbc: mbc,
+            info: info,
+            save_path: None,
+            ram_dirty: false,
+        })
+    }
+
+    // The rom bank mapped at a rom address, wrapped to the banks the rom has
+    pub fn rom_bank_at(&self, address: usize) -> usize {
+        let banks = (self.rom.len() / 0x4000).max(1);
+        self.mbc.rom_bank_at(address) % banks
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        self.mbc.tick(cycles);
+    }
+
+    // Battery backed state: the ram followed by anything the mbc keeps, like
+    // the MBC3 clock
+    pub fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend(self.mbc.save_state());
+        data
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+        if data.len() > self.ram.len() {
+            self.mbc.load_state(&data[self.ram.len()..]);
+        }
+    }
+
+    // Picks up `<rom>.sav` for battery backed carts, if there is one
+    pub fn attach_save_file(&mut self, rom_path: &Path) {
+        if !self.battery {
+            return;
+        }
+        let save_path = rom_path.with_extension("sav");
+        if let Ok(data) = fs::read(&save_path) {
+            println!("Loaded save file {}", save_path.display());
+            self.load_save_data(&data);
+        }
+        self.save_path = Some(save_path);
+    }
+
+    // Writes the battery backed ram out if it changed since the last flush
+    pub fn flush_save_file(&mut self) {
+        if self.ram_dirty {
+            self.write_save_file();
+        }
+    }
+
+    pub fn write_save_file(&mut self) {
+        let data = self.save_data();
+        if data.is_empty() {
+            return;
+        }
+        if let Some(ref save_path) = self.save_path {
+            if let Err(e) = fs::write(save_path, data) {
+                println!("Failed to write save file {}: {}", save_path.display(), e);
+                return;
+            }
+        }
+        self.ram_dirty = false;
+    }
+}
+
+// Size of the external ram given by the header byte at 0x149
+pub fn ram_size(code: u8) -> usize {
+    match code {
+        1 => 0x800,
+        2 => 0x2000,
+        3 => 0x8000,
+        4 => 0x20000,
+        5 => 0x10000,
+        _ => 0,
+    }
+}
+
+impl Region for Cartridge {
+    fn read(&mut self, address: usize) -> u8 {
+        match address {
+            0x0000...0x7FFF => self.mbc.read_rom(&self.rom, address),
+            _ => self.mbc.read_ram(&self.ram, address),
+        }
+    }
+
+    fn write(&mut self, address: usize, val: u8) {
+        match address {
+            0x0000...0x7FFF => {
+                let was_enabled = self.mbc.ram_enabled();
+                self.mbc.write_control(address, val);
+                // Games disable ram once they're done saving, a good time to flush
+                if was_enabled && !self.mbc.ram_enabled() {
+                    self.flush_save_file();
+                }
+            }
+            _ => {
+                if self.mbc.write_ram(&mut self.ram, address, val) {
+                    self.ram_dirty = true;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// OAM DMA keeps the bus for 160 M-cycles plus 2 of setup
+pub const DMA_CYCLES: u64 = 162 * 4;
+
+// OAM DMA. The copy itself happens all at once, the cpu is just locked out of
+// the bus until the time it would have taken is up.
+#[derive(Default)]
+pub struct Dma {
+    pub source: u8,
+    pub active: bool,
+}
+
+impl Dma {
+    pub fn read(&self) -> u8 {
+        self.source
+    }
+
+    // Returns the address the copy starts from
+    pub fn write(&mut self, val: u8) -> usize {
+        self.source = val;
+        self.active = true;
+        (val as usize) << 8
+    }
+
+    pub fn finish(&mut self) {
+        self.active = false;
+    }
+}
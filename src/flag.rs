@@ -1,4 +1,4 @@
-use cpu::*;
+use sm83::CpuBus;
 use register::*;
 
 #[derive(Debug, Clone, Copy)]
@@ -41,27 +41,27 @@ impl<A: FlagSetting, B: FlagSetting, C: FlagSetting, D: FlagSetting> FlagSetting
     }
 }
 
-pub fn mod_flags<T: FlagSetting>(settings: T, cpu: &mut Cpu) {
+pub fn mod_flags<T: FlagSetting, S: CpuBus>(settings: T, gb: &mut S) {
     write_register(Register::F,
-                   settings.mod_flag(read_register(Register::F, cpu)),
-                   cpu)
+                   settings.mod_flag(read_register(Register::F, gb)),
+                   gb)
 }
 
 // set if bool is true, reset if false
-pub fn bool_set(flag: Flag, b: bool, cpu: &mut Cpu) {
-    mod_flags((flag, b), cpu)
+pub fn bool_set<S: CpuBus>(flag: Flag, b: bool, gb: &mut S) {
+    mod_flags((flag, b), gb)
 }
 
-pub fn set(flag: Flag, cpu: &mut Cpu) -> () {
-    mod_flags((flag, true), cpu)
+pub fn set<S: CpuBus>(flag: Flag, gb: &mut S) -> () {
+    mod_flags((flag, true), gb)
 }
 
-pub fn reset(flag: Flag, cpu: &mut Cpu) -> () {
-    mod_flags((flag, false), cpu)
+pub fn reset<S: CpuBus>(flag: Flag, gb: &mut S) -> () {
+    mod_flags((flag, false), gb)
 }
 
-pub fn is_set(flag: Flag, cpu: &mut Cpu) -> bool {
-    read_register(Register::F, cpu) & flag_bit(flag) != 0
+pub fn is_set<S: CpuBus>(flag: Flag, gb: &mut S) -> bool {
+    read_register(Register::F, gb) & flag_bit(flag) != 0
 }
 
 pub fn flag_bit(flag: Flag) -> u8 {
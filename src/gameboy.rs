@@ -0,0 +1,527 @@
+extern crate image;
+use self::image::{ImageBuffer, Rgba};
+use apu;
+use apu::Apu;
+use bus::{Bus, MemoryBus, Region};
+use cartridge::{read_file, Cartridge, LoadError};
+use dma::{Dma, DMA_CYCLES};
+use header::header_checksum;
+use instr;
+use interrupt;
+use interrupt::Interrupt;
+use joypad::Joypad;
+use lcd;
+use lcd::{ScreenMode, LCDC};
+use opcode;
+use opcode::DecodeCache;
+use ppu;
+use ppu::Ppu;
+use scheduler::{Event, Scheduler};
+use serial::{Serial, SERIAL_BIT_CYCLES};
+use sm83::{CpuBus, Lockup, Sm83};
+use std::path::Path;
+use timer::Timer;
+
+pub const SCREEN_WIDTH: u32 = 160;
+pub const SCREEN_HEIGHT: u32 = 144;
+pub const CPU_HZ: usize = 4194304;
+pub const LINE_SCAN_CYCLES: usize = 456;
+pub const FRAME_CYCLES: usize = 70224;
+pub const APU_STEP_CYCLES: usize = 8192; // 512hz frame sequencer
+
+// IO registers as the DMG boot rom leaves them, from pandocs
+const POST_BOOT_IO: [(usize, u8); 33] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF26, 0xF1), // NR52
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF45, 0x00), // LYC
+    (0xFF47, 0xFC), // BGP
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+];
+
+// The whole console. It owns every component and wires them together: the
+// memory map, the clock they all share and the interrupts they raise.
+pub struct GameBoy {
+    pub cpu: Sm83,
+    pub bus: MemoryBus,
+    pub cartridge: Cartridge,
+    pub ppu: Ppu,
+    pub timer: Timer,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub dma: Dma,
+    pub apu: Apu,
+    pub scheduler: Scheduler,
+    pub interrupt_flag: u8,
+    pub interrupt_enable: u8,
+    pub cart_loaded: bool,
+    pub decode_cache: DecodeCache,
+    pub next_addr: usize,
+    pub frame: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    pub screen_buffer: [u8; 256 * 256],
+}
+
+impl Default for GameBoy {
+    fn default() -> GameBoy {
+        let mut frame = ImageBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        for (_, _, pixel) in frame.enumerate_pixels_mut() {
+            *pixel = Rgba([0, 0, 0, 255]);
+        }
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(APU_STEP_CYCLES as u64, Event::FrameSequencer);
+        GameBoy {
+            cpu: Default::default(),
+            bus: Default::default(),
+            cartridge: Default::default(),
+            ppu: Default::default(),
+            timer: Default::default(),
+            joypad: Default::default(),
+            serial: Default::default(),
+            dma: Default::default(),
+            apu: Default::default(),
+            scheduler: scheduler,
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            cart_loaded: false,
+            decode_cache: Default::default(),
+            next_addr: 0,
+            frame: frame,
+            screen_buffer: [0; 256 * 256],
+        }
+    }
+}
+
+impl GameBoy {
+    pub fn new() -> GameBoy {
+        Default::default()
+    }
+
+    // Clocks run since power on
+    pub fn cycles(&self) -> u64 {
+        self.scheduler.now()
+    }
+
+    // Starts at the cartridge entry point with the state the boot rom leaves,
+    // for when there's no boot rom dump around
+    pub fn skip_boot_rom(&mut self) {
+        self.bus.has_booted = true;
+        self.cpu.init_post_boot_registers();
+        self.cpu.pc = 0x0100;
+        self.next_addr = 0x0100;
+        for &(address, val) in POST_BOOT_IO.iter() {
+            match address {
+                // Writing these would trigger the sound channels
+                0xFF10...0xFF3F => self.apu.set_reg(address, val),
+                _ => self.write_mapped(address, val),
+            }
+        }
+        // DIV reads 0xAB, TIMA, TMA and TAC are all 0
+        self.timer = Timer::default();
+        self.timer.counter = 0xABCC;
+        self.timer.synced_at = self.cycles();
+        self.sync_timer();
+    }
+
+    pub fn load_bootrom(&mut self, path: &str) -> Result<(), LoadError> {
+        let boot_rom = read_file(Path::new(path))?;
+        if boot_rom.len() < 0x100 {
+            return Err(LoadError::TooSmall(boot_rom.len()));
+        }
+        self.bus.boot_rom = boot_rom;
+        Ok(())
+    }
+
+    // Loads the rom at `path` along with its `.sav` file if it has a battery
+    pub fn load_cart(&mut self, path: &str) -> Result<(), LoadError> {
+        let rom = read_file(Path::new(path))?;
+        self.load_cart_from_bytes(&rom)?;
+        self.cartridge.attach_save_file(Path::new(path));
+        Ok(())
+    }
+
+    pub fn load_cart_from_bytes(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let cartridge = Cartridge::new(rom.to_vec())?;
+        // The boot rom locks up on a bad header checksum, without it the
+        // checksum is never looked at and the game runs fine
+        let boot_rom_runs = !self.bus.has_booted && !self.bus.boot_rom.is_empty();
+        if boot_rom_runs && !cartridge.info.header_checksum_ok {
+            return Err(LoadError::BadHeader {
+                expected: cartridge.info.header_checksum,
+                found: header_checksum(rom),
+            });
+        }
+        self.cartridge.write_save_file();
+        self.cartridge = cartridge;
+        self.decode_cache.clear();
+        self.cart_loaded = true;
+        Ok(())
+    }
+
+    // Set once the cpu has locked up on an illegal opcode
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.cpu.lockup
+    }
+
+    // Returns true once for every frame the PPU has finished since the last call
+    pub fn take_frame(&mut self) -> bool {
+        let ready = self.ppu.frame_ready;
+        self.ppu.frame_ready = false;
+        ready
+    }
+
+    // Runs until the PPU finishes a frame. No frame is ever finished while the
+    // LCD is off, so give up after a frame's worth of cycles. The clock stands
+    // still in STOP mode, so that ends the frame early too.
+    pub fn run_frame(&mut self) -> usize {
+        let mut elapsed = 0;
+        self.ppu.frame_ready = false;
+        while elapsed < FRAME_CYCLES && !self.ppu.frame_ready {
+            elapsed += self.step_instruction();
+            if self.cpu.stopped {
+                break;
+            }
+        }
+        elapsed
+    }
+
+    // Runs for at least `cycles` clocks, returns the number actually run.
+    // Returns early while the gb is in STOP mode.
+    pub fn run_cycles(&mut self, cycles: usize) -> usize {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            elapsed += self.step_instruction();
+            if self.cpu.stopped {
+                break;
+            }
+        }
+        elapsed
+    }
+
+    // Executes one instruction (or one halted step) along with any interrupt
+    // dispatch it causes, returns the number of clocks taken
+    pub fn step_instruction(&mut self) -> usize {
+        let start = self.cycles();
+        if self.cpu.lockup.is_some() {
+            self.tick(4);
+        } else if self.cpu.stopped {
+            self.step_stopped();
+        } else if self.cpu.halted {
+            self.step_halted();
+        } else {
+            self.step_running();
+        }
+        if let Some(ly) = self.ppu.finished_line.take() {
+            ppu::render_scanline(ly, &mut self.screen_buffer, &mut self.frame, &self.ppu);
+        }
+        (self.cycles() - start) as usize
+    }
+
+    // Everything, the lcd and timers included, stands still until a button
+    // on a selected joypad line is pressed, so no clocks pass
+    fn step_stopped(&mut self) {
+        if self.joypad.line_low() {
+            self.cpu.stopped = false;
+        }
+    }
+
+    // Only an event can raise an interrupt while halted, so unless one is
+    // already pending the cpu sleeps straight through to the nearest one.
+    // Events don't all fall on M-cycle boundaries, the cpu wakes on the
+    // first one after.
+    fn step_halted(&mut self) {
+        let cycles = if interrupt::is_pending(self) {
+            4
+        } else {
+            let until_next = self.scheduler.cycles_until_next().unwrap_or(4).max(4);
+            ((until_next + 3) & !3) as usize
+        };
+        self.tick(cycles);
+        let interrupt_addr = interrupt::exec_halt_interrupts(self.next_addr, self);
+        if !self.cpu.halted {
+            // Waking up takes 4 clocks, the dispatch ticks its own 20
+            self.tick(4);
+        }
+        if let Some(interrupt_addr) = interrupt_addr {
+            self.next_addr = interrupt_addr;
+        }
+    }
+
+    fn step_running(&mut self) {
+        let start = self.cycles();
+        let instr = if self.cpu.halt_bug {
+            self.cpu.halt_bug = false;
+            opcode::decode_halt_bug(self.next_addr, self)
+        } else {
+            opcode::decode_cached(self.next_addr, self)
+        };
+        // Fetching the opcode and its arguments takes an M-cycle per byte, the
+        // instruction's own memory accesses and taken branches tick as they
+        // happen
+        self.tick(4 * instr.length);
+
+        self.next_addr += instr.length;
+        self.next_addr = instr::exec(instr, self.next_addr, self);
+        // Whatever is left are internal cycles
+        let ticked = (self.cycles() - start) as usize;
+        if ticked < instr.cycles {
+            self.tick(instr.cycles - ticked);
+        }
+        // A locked up cpu never services interrupts again
+        if self.cpu.lockup.is_some() {
+            return;
+        }
+
+        if let Some(interrupt_addr) = interrupt::exec_interrupts(self.next_addr, self) {
+            self.next_addr = interrupt_addr;
+        }
+    }
+
+    // Moves the clock forward by `cycles` and fires every event that came due
+    // on the way
+    pub fn tick(&mut self, cycles: usize) {
+        self.scheduler.advance(cycles as u64);
+        while let Some((timestamp, event)) = self.scheduler.pop_due() {
+            self.handle_event(timestamp, event);
+        }
+        self.cartridge.tick(cycles);
+    }
+
+    // Events are handled relative to when they were due so they don't drift
+    fn handle_event(&mut self, timestamp: u64, event: Event) {
+        match event {
+            Event::OamScanEnd | Event::TransferEnd | Event::LineEnd | Event::LyReset => {
+                self.interrupt_flag |=
+                    lcd::handle_event(event, timestamp, &mut self.ppu, &mut self.scheduler);
+            }
+            Event::TimerOverflow => self.sync_timer(),
+            Event::SerialBit => {
+                if self.serial.shift_bit() {
+                    Interrupt::Serial.request(self);
+                } else {
+                    self.scheduler.schedule(timestamp + SERIAL_BIT_CYCLES, Event::SerialBit);
+                }
+            }
+            Event::DmaEnd => self.dma.finish(),
+            Event::FrameSequencer => {
+                apu::step(&mut self.apu);
+                self.scheduler
+                    .schedule(timestamp + APU_STEP_CYCLES as u64, Event::FrameSequencer);
+            }
+        }
+    }
+
+    // Brings the pixel fifo up to the clock before a register it reads changes
+    fn sync_ppu(&mut self) {
+        let now = self.scheduler.now();
+        self.ppu.sync(now);
+    }
+
+    // Brings the timer up to the clock and schedules its next overflow
+    pub fn sync_timer(&mut self) {
+        if self.timer.sync(self.scheduler.now()) {
+            Interrupt::Timer.request(self);
+        }
+        match self.timer.next_interrupt() {
+            Some(timestamp) => self.scheduler.schedule(timestamp, Event::TimerOverflow),
+            None => self.scheduler.cancel(Event::TimerOverflow),
+        }
+    }
+
+    pub fn write_timer(&mut self, address: usize, val: u8) {
+        self.sync_timer();
+        self.timer.write(address, val);
+        // The write may have moved the next overflow
+        self.sync_timer();
+    }
+
+    // The memory map without the cpu's timing or locking, each address goes
+    // to the component that owns it
+    pub fn read_mapped(&mut self, address: usize) -> u8 {
+        match address {
+            0x0000...0x00FF if !self.bus.has_booted => self.bus.read(address),
+            0x0000...0x7FFF | 0xA000...0xBFFF => self.cartridge.read(address),
+            0x8000...0x9FFF | 0xFE00...0xFE9F => self.ppu.read(address),
+            0xFEA0...0xFEFF => 0, // Unused memory
+            0xFF00 => self.joypad.read(),
+            0xFF01...0xFF02 => self.serial.read(address),
+            0xFF04...0xFF07 => {
+                self.sync_timer();
+                self.timer.read(address)
+            }
+            0xFF0F => interrupt::read_if(self),
+            0xFF10...0xFF3F => self.apu.read(address),
+            0xFF46 => self.dma.read(),
+            0xFF40...0xFF4B => self.ppu.read(address),
+            0xFFFF => interrupt::read_ie(self),
+            _ => self.bus.read(address),
+        }
+    }
+
+    pub fn write_mapped(&mut self, address: usize, val: u8) {
+        match address {
+            0x0000...0x7FFF | 0xA000...0xBFFF => self.cartridge.write(address, val),
+            0x8000...0x9FFF | 0xFE00...0xFE9F => {
+                self.ppu.write(address, val, &mut self.scheduler);
+            }
+            0xFEA0...0xFEFF => {}
+            0xFF00 => self.joypad.write(val),
+            0xFF01...0xFF02 => {
+                if self.serial.write(address, val) {
+                    self.scheduler.schedule_in(SERIAL_BIT_CYCLES, Event::SerialBit);
+                }
+            }
+            0xFF04...0xFF07 => self.write_timer(address, val),
+            0xFF0F => interrupt::write_if(val, self),
+            0xFF10...0xFF3F => self.apu.write(address, val),
+            0xFF46 => self.dma_transfer(val),
+            0xFF40...0xFF4B => {
+                self.sync_ppu();
+                self.interrupt_flag |= self.ppu.write(address, val, &mut self.scheduler);
+            }
+            0xFF50 => {
+                self.bus.has_booted = true;
+                // initialize memory based on pandocs
+                self.joypad.write(0xCF);
+                self.cpu.init_post_boot_registers();
+            }
+            0xFFFF => interrupt::write_ie(val, self),
+            _ => self.bus.write(address, val),
+        }
+    }
+
+    fn dma_transfer(&mut self, val: u8) {
+        let source_addr = self.dma.write(val);
+        self.scheduler.schedule_in(DMA_CYCLES, Event::DmaEnd);
+        for i in 0..0xA0 {
+            self.ppu.oam[i] = self.read_mapped(source_addr + i);
+        }
+    }
+}
+
+// Memory as seen by the cpu: the ppu and dma lock out parts of it, and every
+// access takes an M-cycle.
+impl Bus for GameBoy {
+    // The rest of the system is ticked first so the access sees the state at
+    // the end of the M-cycle
+    fn read(&mut self, address: usize) -> u8 {
+        self.tick(4);
+        let safe_to_read = !self.dma.active
+            && match address {
+                0x8000...0x9FFF => {
+                    !LCDC::Power.is_set(&self.ppu) || !ScreenMode::Transferring.is_set(&self.ppu)
+                }
+                0xFEA0...0xFEFF => false,
+                0xFE00...0xFE9F => {
+                    !LCDC::Power.is_set(&self.ppu)
+                        || (ScreenMode::HBlank.is_set(&self.ppu) || ScreenMode::VBlank.is_set(&self.ppu))
+                }
+                _ => true,
+            };
+        if safe_to_read {
+            self.read_mapped(address)
+        } else {
+            match address {
+                0xFF80...0xFFFE => self.bus.read(address),
+                _ => 0xFF,
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, val: u8) {
+        self.tick(4);
+        let safe_to_write = !self.dma.active
+            && match address {
+                0x8000...0x9FFF => {
+                    !LCDC::Power.is_set(&self.ppu) || !ScreenMode::Transferring.is_set(&self.ppu)
+                }
+                0xFE00...0xFE9F => {
+                    // OAM
+                    !LCDC::Power.is_set(&self.ppu)
+                        || (ScreenMode::HBlank.is_set(&self.ppu) || ScreenMode::VBlank.is_set(&self.ppu))
+                }
+                0xFEA0...0xFEFF => false, //Unused memory
+                _ => true,
+            };
+        if safe_to_write {
+            self.write_mapped(address, val);
+        } else if self.dma.active {
+            match address {
+                0xFF80...0xFFFE => self.bus.write(address, val),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl CpuBus for GameBoy {
+    fn cpu(&mut self) -> &mut Sm83 {
+        &mut self.cpu
+    }
+
+    fn peek(&mut self, address: usize) -> u8 {
+        self.read_mapped(address)
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        GameBoy::tick(self, cycles)
+    }
+
+    fn interrupt_flag(&self) -> u8 {
+        self.interrupt_flag
+    }
+
+    fn set_interrupt_flag(&mut self, val: u8) {
+        self.interrupt_flag = val;
+    }
+
+    fn interrupt_enable(&self) -> u8 {
+        self.interrupt_enable
+    }
+
+    fn set_interrupt_enable(&mut self, val: u8) {
+        self.interrupt_enable = val;
+    }
+
+    fn joypad_line_low(&self) -> bool {
+        self.joypad.line_low()
+    }
+
+    fn reset_div(&mut self) {
+        self.write_timer(0xFF04, 0);
+    }
+
+    fn rom_bank_at(&self, address: usize) -> Option<usize> {
+        if address < 0x8000 && self.bus.has_booted {
+            Some(self.cartridge.rom_bank_at(address))
+        } else {
+            None
+        }
+    }
+}
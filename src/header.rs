@@ -0,0 +1,169 @@
+use cartridge::{ram_size, LoadError};
+use mbc::MBC2_RAM_SIZE;
+
+// The header lives at 0x100-0x14F, a rom has to at least cover it
+pub const HEADER_END: usize = 0x150;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CgbSupport {
+    DmgOnly,
+    Supported,
+    CgbOnly,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Licensee {
+    Old(u8),
+    // 0x14B = 0x33 means the two ascii characters at 0x144 are used instead
+    New(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CartridgeInfo {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub mapper: Mapper,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_ok: bool,
+    pub global_checksum: u16,
+    pub global_checksum_ok: bool,
+}
+
+impl Default for CartridgeInfo {
+    fn default() -> CartridgeInfo {
+        CartridgeInfo {
+            title: String::new(),
+            manufacturer: None,
+            cgb: CgbSupport::DmgOnly,
+            sgb: false,
+            cartridge_type: 0,
+            mapper: Mapper::RomOnly,
+            battery: false,
+            timer: false,
+            rumble: false,
+            rom_size: 0,
+            ram_size: 0,
+            licensee: Licensee::Old(0),
+            version: 0,
+            header_checksum: 0,
+            header_checksum_ok: false,
+            global_checksum: 0,
+            global_checksum_ok: false,
+        }
+    }
+}
+
+impl CartridgeInfo {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeInfo, LoadError> {
+        if rom.len() < HEADER_END {
+            return Err(LoadError::TooSmall(rom.len()));
+        }
+        let cartridge_type = rom[0x147];
+        let (mapper, battery, timer, rumble) = match cartridge_type {
+            0x00 | 0x08 => (Mapper::RomOnly, false, false, false),
+            0x09 => (Mapper::RomOnly, true, false, false),
+            0x01 | 0x02 => (Mapper::Mbc1, false, false, false),
+            0x03 => (Mapper::Mbc1, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false),
+            0x06 => (Mapper::Mbc2, true, false, false),
+            0x0F | 0x10 => (Mapper::Mbc3, true, true, false),
+            0x11 | 0x12 => (Mapper::Mbc3, false, false, false),
+            0x13 => (Mapper::Mbc3, true, false, false),
+            0x19 | 0x1A => (Mapper::Mbc5, false, false, false),
+            0x1B => (Mapper::Mbc5, true, false, false),
+            0x1C | 0x1D => (Mapper::Mbc5, false, false, true),
+            0x1E => (Mapper::Mbc5, true, false, true),
+            t => return Err(LoadError::UnsupportedMapper(t)),
+        };
+
+        let cgb = match rom[0x143] {
+            0xC0 => CgbSupport::CgbOnly,
+            0x80 => CgbSupport::Supported,
+            _ => CgbSupport::DmgOnly,
+        };
+        // Newer carts shortened the title to make room for the cgb flag and
+        // a manufacturer code
+        let title_end = cond!(cgb == CgbSupport::DmgOnly, 0x144, 0x143);
+        let manufacturer = &rom[0x13F..0x143];
+        let manufacturer = cond!(
+            cgb != CgbSupport::DmgOnly && manufacturer.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()),
+            Some(String::from_utf8_lossy(manufacturer).into_owned()),
+            None
+        );
+
+        let licensee = match rom[0x14B] {
+            0x33 => Licensee::New(String::from_utf8_lossy(&rom[0x144..0x146]).into_owned()),
+            code => Licensee::Old(code),
+        };
+
+        let rom_size = match rom[0x148] {
+            code @ 0...8 => 0x8000 << code,
+            _ => rom.len(),
+        };
+        let ram_size = cond!(mapper == Mapper::Mbc2, MBC2_RAM_SIZE, ram_size(rom[0x149]));
+
+        let computed_header = header_checksum(rom);
+        let header_checksum = rom[0x14D];
+        let global_checksum = ((rom[0x14E] as u16) << 8) | rom[0x14F] as u16;
+        let computed_global = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+            .fold(0u16, |x, (_, &b)| x.wrapping_add(b as u16));
+
+        Ok(CartridgeInfo {
+            title: ascii_string(&rom[0x134..title_end]),
+            manufacturer: manufacturer,
+            cgb: cgb,
+            sgb: rom[0x146] == 0x03,
+            cartridge_type: cartridge_type,
+            mapper: mapper,
+            battery: battery,
+            timer: timer,
+            rumble: rumble,
+            rom_size: rom_size,
+            ram_size: ram_size,
+            licensee: licensee,
+            version: rom[0x14C],
+            header_checksum: header_checksum,
+            header_checksum_ok: header_checksum == computed_header,
+            global_checksum: global_checksum,
+            global_checksum_ok: global_checksum == computed_global,
+        })
+    }
+}
+
+// The boot rom refuses to start a cartridge unless this matches 0x14D
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14D]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+// Titles are padded with zeros
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| cond!(c.is_ascii_graphic() || c == b' ', c as char, '?'))
+        .collect()
+}
@@ -1,171 +1,174 @@
-use cpu::*;
+use sm83::CpuBus;
 use register::*;
 use flag;
 use flag::Flag;
+use interrupt;
 use opcode::*;
+use sm83::Lockup;
 use utility::*;
+use std::marker::PhantomData;
 
-pub fn ld_m(reg: Register, val: u16, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    write_multi_register(reg, val, cpu);
+pub fn ld_m<S: CpuBus>(reg: Register, val: u16, curr_addr: usize, gb: &mut S) -> usize {
+    write_multi_register(reg, val, gb);
     curr_addr
 }
 
-pub fn ldhl_sp(val: i8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let new_val = wrapping_off_u16_i8(cpu.sp, val);
+pub fn ldhl_sp<S: CpuBus>(val: i8, curr_addr: usize, gb: &mut S) -> usize {
+    let new_val = wrapping_off_u16_i8(gb.cpu().sp, val);
     flag::mod_flags(((Flag::Z, false),
                      (Flag::N, false),
-                     (Flag::H, 0xF < ((cpu.sp & 0xF) + ((val as u8) & 0xF) as u16)),
-                     (Flag::C, 0xFF < ((cpu.sp & 0xFF) + ((val as u8) as u16)))),
-                    cpu);
-    write_multi_register(Register::HL, new_val, cpu);
+                     (Flag::H, 0xF < ((gb.cpu().sp & 0xF) + ((val as u8) & 0xF) as u16)),
+                     (Flag::C, 0xFF < ((gb.cpu().sp & 0xFF) + ((val as u8) as u16)))),
+                    gb);
+    write_multi_register(Register::HL, new_val, gb);
     curr_addr
 }
 
-pub fn add_to_sp(num: i8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let new_sp = wrapping_off_u16_i8(cpu.sp, num);
+pub fn add_to_sp<S: CpuBus>(num: i8, curr_addr: usize, gb: &mut S) -> usize {
+    let new_sp = wrapping_off_u16_i8(gb.cpu().sp, num);
     flag::mod_flags(((Flag::N, false),
-                     (Flag::H, 0xF < ((cpu.sp & 0xF) + ((num as u8) & 0xF) as u16)),
-                     (Flag::C, 0xFF < ((cpu.sp & 0xFF) + ((num as u8) as u16))),
+                     (Flag::H, 0xF < ((gb.cpu().sp & 0xF) + ((num as u8) & 0xF) as u16)),
+                     (Flag::C, 0xFF < ((gb.cpu().sp & 0xFF) + ((num as u8) as u16))),
                      (Flag::Z, false)),
-                    cpu);
-    cpu.sp = new_sp;
+                    gb);
+    gb.cpu().sp = new_sp;
     curr_addr
 }
 
 
-pub fn add_to_hl(num: u16, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let hl_val = read_multi_register(Register::HL, cpu);
-    write_multi_register(Register::HL, hl_val.wrapping_add(num), cpu);
+pub fn add_to_hl<S: CpuBus>(num: u16, curr_addr: usize, gb: &mut S) -> usize {
+    let hl_val = read_multi_register(Register::HL, gb);
+    write_multi_register(Register::HL, hl_val.wrapping_add(num), gb);
     flag::mod_flags(((Flag::N, false),
                      (Flag::H, 0x0FFF < ((hl_val & 0x0FFF) + (num & 0x0FFF))),
                      (Flag::C, 0xFFFF < (hl_val as u32 + num as u32))),
-                    cpu);
+                    gb);
     curr_addr
 }
 
-pub fn a_or_val(num: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    cpu.a |= num;
-    flag::mod_flags(((Flag::Z, cpu.a == 0), (Flag::N, false), (Flag::H, false), (Flag::C, false)),
-                    cpu);
+pub fn a_or_val<S: CpuBus>(num: u8, curr_addr: usize, gb: &mut S) -> usize {
+    gb.cpu().a |= num;
+    flag::mod_flags(((Flag::Z, gb.cpu().a == 0), (Flag::N, false), (Flag::H, false), (Flag::C, false)),
+                    gb);
     curr_addr
 }
 
-pub fn a_and_val(num: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    cpu.a &= num;
-    flag::mod_flags(((Flag::Z, cpu.a == 0), (Flag::N, false), (Flag::H, true), (Flag::C, false)),
-                    cpu);
+pub fn a_and_val<S: CpuBus>(num: u8, curr_addr: usize, gb: &mut S) -> usize {
+    gb.cpu().a &= num;
+    flag::mod_flags(((Flag::Z, gb.cpu().a == 0), (Flag::N, false), (Flag::H, true), (Flag::C, false)),
+                    gb);
     curr_addr
 }
 
-pub fn add_to_a(num: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let a_val = cpu.a;
+pub fn add_to_a<S: CpuBus>(num: u8, curr_addr: usize, gb: &mut S) -> usize {
+    let a_val = gb.cpu().a;
     let res = a_val.wrapping_add(num);
     flag::mod_flags(((Flag::N, false),
                      (Flag::Z, res == 0),
                      (Flag::H, 0x10 <= ((a_val & 0x0F) + (num & 0x0F))),
                      (Flag::C, 0x0100 <= (a_val as u16 + num as u16))),
-                    cpu);
-    cpu.a = res;
+                    gb);
+    gb.cpu().a = res;
     curr_addr
 }
 
-pub fn addc_to_a(num: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let a_val = cpu.a;
-    let carry = if flag::is_set(Flag::C, cpu) { 1 } else { 0 };
+pub fn addc_to_a<S: CpuBus>(num: u8, curr_addr: usize, gb: &mut S) -> usize {
+    let a_val = gb.cpu().a;
+    let carry = if flag::is_set(Flag::C, gb) { 1 } else { 0 };
     let res = a_val.wrapping_add(num).wrapping_add(carry);
     flag::mod_flags(((Flag::N, false),
                      (Flag::Z, res == 0),
                      (Flag::H, 0x10 <= ((a_val & 0x0F) + (num & 0x0F) + carry)),
                      (Flag::C, 0x0100 <= (a_val as u16 + num as u16 + carry as u16))),
-                    cpu);
-    cpu.a = res;
+                    gb);
+    gb.cpu().a = res;
     curr_addr
 }
 
-pub fn sub_from_a(num: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let a_val = cpu.a;
+pub fn sub_from_a<S: CpuBus>(num: u8, curr_addr: usize, gb: &mut S) -> usize {
+    let a_val = gb.cpu().a;
     flag::mod_flags(((Flag::N, true),
                      (Flag::Z, a_val == num),
                      (Flag::H, (a_val & 0x0F) < (num & 0x0F)),
                      (Flag::C, a_val < num)),
-                    cpu);
-    cpu.a = a_val.wrapping_sub(num);
+                    gb);
+    gb.cpu().a = a_val.wrapping_sub(num);
     curr_addr
 }
 
-pub fn subc_from_a(num: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let a_val = cpu.a;
-    let carry = if flag::is_set(Flag::C, cpu) { 1 } else { 0 };
+pub fn subc_from_a<S: CpuBus>(num: u8, curr_addr: usize, gb: &mut S) -> usize {
+    let a_val = gb.cpu().a;
+    let carry = if flag::is_set(Flag::C, gb) { 1 } else { 0 };
     let res = a_val.wrapping_sub(num).wrapping_sub(carry);
     flag::mod_flags(((Flag::N, true),
                      (Flag::Z, res == 0),
                      (Flag::H, (a_val & 0x0F) < ((num & 0x0F) + carry)),
                      (Flag::C, (a_val as u16) < (num as u16 + carry as u16))),
-                    cpu);
-    cpu.a = res;
+                    gb);
+    gb.cpu().a = res;
     curr_addr
 }
 
-pub fn ld(reg: Register, val: u8, curr_addr: usize, cpu: &mut Cpu) -> usize {
-    write_register(reg, val, cpu);
+pub fn ld<S: CpuBus>(reg: Register, val: u8, curr_addr: usize, gb: &mut S) -> usize {
+    write_register(reg, val, gb);
     curr_addr
 }
 
-pub fn ld_r(to_reg: Register, from_reg: Register, curr_addr: usize, cpu: &mut Cpu) -> usize {
+pub fn ld_r<S: CpuBus>(to_reg: Register, from_reg: Register, curr_addr: usize, gb: &mut S) -> usize {
     match (to_reg, from_reg) {
         (Register::HLP, _) |
         (Register::HLM, _) => {
-            write_register(Register::HL_ADDR, read_register(from_reg, cpu), cpu);
+            write_register(Register::HL_ADDR, read_register(from_reg, gb), gb);
 
-            let hl = read_multi_register(Register::HL, cpu);
+            let hl = read_multi_register(Register::HL, gb);
             let new_hl = match to_reg {
                 Register::HLP => hl.wrapping_add(1),
                 _ => hl.wrapping_sub(1),
             };
-            write_multi_register(Register::HL, new_hl, cpu);
+            write_multi_register(Register::HL, new_hl, gb);
             curr_addr
         }
         (_, Register::HLP) |
         (_, Register::HLM) => {
-            let hl_val = read_register(Register::HL_ADDR, cpu);
-            write_register(to_reg, hl_val, cpu);
+            let hl_val = read_register(Register::HL_ADDR, gb);
+            write_register(to_reg, hl_val, gb);
 
-            let hl_addr = read_multi_register(Register::HL, cpu);
+            let hl_addr = read_multi_register(Register::HL, gb);
             let new_hl_addr = match from_reg {
                 Register::HLP => hl_addr.wrapping_add(1),
                 _ => hl_addr.wrapping_sub(1),
             };
-            write_multi_register(Register::HL, new_hl_addr, cpu);
+            write_multi_register(Register::HL, new_hl_addr, gb);
             curr_addr
         } 
         (Register::CH, _) => {
-            write_register(to_reg, read_register(from_reg, cpu), cpu);
+            write_register(to_reg, read_register(from_reg, gb), gb);
             curr_addr
         }
         (_, Register::CH) => {
-            write_register(to_reg, read_register(from_reg, cpu), cpu);
+            write_register(to_reg, read_register(from_reg, gb), gb);
             curr_addr
         }
         _ => {
-            write_register(to_reg, read_register(from_reg, cpu), cpu);
+            write_register(to_reg, read_register(from_reg, gb), gb);
             curr_addr
         }
     }
 }
 
-pub fn bit(bit_pos: u8, reg: Register, cpu: &mut Cpu) -> () {
-    flag::set(Flag::H, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::bool_set(Flag::Z, read_register(reg, cpu) & (1 << bit_pos) == 0, cpu);
+pub fn bit<S: CpuBus>(bit_pos: u8, reg: Register, gb: &mut S) -> () {
+    flag::set(Flag::H, gb);
+    flag::reset(Flag::N, gb);
+    flag::bool_set(Flag::Z, read_register(reg, gb) & (1 << bit_pos) == 0, gb);
 }
 
-pub fn daa(curr_addr: usize, cpu: &mut Cpu) -> usize {
-    let mut a_val = cpu.a as u16;
-    let h_flag = flag::is_set(Flag::H, cpu);
-    let c_flag = flag::is_set(Flag::C, cpu);
+pub fn daa<S: CpuBus>(curr_addr: usize, gb: &mut S) -> usize {
+    let mut a_val = gb.cpu().a as u16;
+    let h_flag = flag::is_set(Flag::H, gb);
+    let c_flag = flag::is_set(Flag::C, gb);
     let low_n = a_val & 0xF;
 
-    if !flag::is_set(Flag::N, cpu) {
+    if !flag::is_set(Flag::N, gb) {
         if h_flag || low_n > 9 {
             a_val = a_val.wrapping_add(0x06);
         }
@@ -181,373 +184,639 @@ pub fn daa(curr_addr: usize, cpu: &mut Cpu) -> usize {
         }
     }
 
-    flag::reset(Flag::H, cpu);
-    flag::reset(Flag::Z, cpu);
+    flag::reset(Flag::H, gb);
+    flag::reset(Flag::Z, gb);
     if (a_val & 0x100) == 0x100 {
-        flag::set(Flag::C, cpu);
+        flag::set(Flag::C, gb);
     }
     a_val &= 0xFF;
     if a_val == 0 {
-        flag::set(Flag::Z, cpu);
+        flag::set(Flag::Z, gb);
     }
-    cpu.a = a_val as u8;
+    gb.cpu().a = a_val as u8;
     curr_addr
 }
 
-pub fn xor_d8(val: u8, cpu: &mut Cpu) -> () {
-    cpu.a ^= val;
-    flag::bool_set(Flag::Z, cpu.a == 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-    flag::reset(Flag::C, cpu);
-}
-
-pub fn cp(num: u8, cpu: &mut Cpu) {
-    let a_val = cpu.a;
-    flag::set(Flag::N, cpu);
-    flag::bool_set(Flag::Z, a_val == num, cpu);
-    flag::bool_set(Flag::H, (a_val & 0x0F) < (num & 0x0F), cpu);
-    flag::bool_set(Flag::C, a_val < num, cpu);
-}
-
-pub fn rl(reg: Register, conditional_z: bool, cpu: &mut Cpu) {
-    let old_c_bit = if flag::is_set(Flag::C, cpu) { 1 } else { 0 };
-    let new_c_bit = read_register(reg, cpu) & 0x80;
-    write_register(reg, (read_register(reg, cpu) << 1) + old_c_bit, cpu);
-    flag::bool_set(Flag::Z, conditional_z && read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, new_c_bit != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn rlc(reg: Register, conditional_z: bool, cpu: &mut Cpu) {
-    let new_c_bit = read_register(reg, cpu) & 0x80;
-    write_register(reg, read_register(reg, cpu).rotate_left(1), cpu);
-    flag::bool_set(Flag::Z, conditional_z && read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, new_c_bit != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn rr(reg: Register, conditional_z: bool, cpu: &mut Cpu) {
-    let old_c_bit = if flag::is_set(Flag::C, cpu) { 1 } else { 0 };
-    let new_c_bit = read_register(reg, cpu) & 1;
-    write_register(reg, (read_register(reg, cpu) >> 1) + (old_c_bit << 7), cpu);
-    flag::bool_set(Flag::Z, conditional_z && read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, new_c_bit != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn sra(reg: Register, cpu: &mut Cpu) {
-    let new_c_bit = read_register(reg, cpu) & 1;
-    let msb = read_register(reg, cpu) & 0b10000000;
-    write_register(reg, (read_register(reg, cpu) >> 1) | msb, cpu);
-    flag::bool_set(Flag::Z, read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, new_c_bit != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn srl(reg: Register, cpu: &mut Cpu) {
-    let lsb = read_register(reg, cpu) & 1;
-    write_register(reg, read_register(reg, cpu) >> 1, cpu);
-    flag::bool_set(Flag::Z, read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, lsb != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn sla(reg: Register, cpu: &mut Cpu) {
-    let msb = read_register(reg, cpu) & 0b10000000;
-    write_register(reg, read_register(reg, cpu) << 1, cpu);
-    flag::bool_set(Flag::Z, read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, msb != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn rrc(reg: Register, conditional_z: bool, cpu: &mut Cpu) {
-    let new_c_bit = read_register(reg, cpu) & 1;
-    write_register(reg, (read_register(reg, cpu) >> 1) + (new_c_bit << 7), cpu);
-    flag::bool_set(Flag::Z, conditional_z && read_register(reg, cpu) == 0, cpu);
-    flag::bool_set(Flag::C, new_c_bit != 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-}
-
-pub fn inc_u8(reg: Register, cpu: &mut Cpu) {
-    let reg_val = read_register(reg, cpu);
+pub fn xor_d8<S: CpuBus>(val: u8, gb: &mut S) -> () {
+    gb.cpu().a ^= val;
+    flag::bool_set(Flag::Z, gb.cpu().a == 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+    flag::reset(Flag::C, gb);
+}
+
+pub fn cp<S: CpuBus>(num: u8, gb: &mut S) {
+    let a_val = gb.cpu().a;
+    flag::set(Flag::N, gb);
+    flag::bool_set(Flag::Z, a_val == num, gb);
+    flag::bool_set(Flag::H, (a_val & 0x0F) < (num & 0x0F), gb);
+    flag::bool_set(Flag::C, a_val < num, gb);
+}
+
+pub fn rl<S: CpuBus>(reg: Register, conditional_z: bool, gb: &mut S) {
+    let old_c_bit = if flag::is_set(Flag::C, gb) { 1 } else { 0 };
+    let val = read_register(reg, gb);
+    let res = (val << 1) + old_c_bit;
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, conditional_z && res == 0, gb);
+    flag::bool_set(Flag::C, val & 0x80 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn rlc<S: CpuBus>(reg: Register, conditional_z: bool, gb: &mut S) {
+    let val = read_register(reg, gb);
+    let res = val.rotate_left(1);
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, conditional_z && res == 0, gb);
+    flag::bool_set(Flag::C, val & 0x80 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn rr<S: CpuBus>(reg: Register, conditional_z: bool, gb: &mut S) {
+    let old_c_bit = if flag::is_set(Flag::C, gb) { 1 } else { 0 };
+    let val = read_register(reg, gb);
+    let res = (val >> 1) + (old_c_bit << 7);
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, conditional_z && res == 0, gb);
+    flag::bool_set(Flag::C, val & 1 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn sra<S: CpuBus>(reg: Register, gb: &mut S) {
+    let val = read_register(reg, gb);
+    let res = (val >> 1) | (val & 0b10000000);
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, res == 0, gb);
+    flag::bool_set(Flag::C, val & 1 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn srl<S: CpuBus>(reg: Register, gb: &mut S) {
+    let val = read_register(reg, gb);
+    let res = val >> 1;
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, res == 0, gb);
+    flag::bool_set(Flag::C, val & 1 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn sla<S: CpuBus>(reg: Register, gb: &mut S) {
+    let val = read_register(reg, gb);
+    let res = val << 1;
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, res == 0, gb);
+    flag::bool_set(Flag::C, val & 0b10000000 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn rrc<S: CpuBus>(reg: Register, conditional_z: bool, gb: &mut S) {
+    let val = read_register(reg, gb);
+    let res = val.rotate_right(1);
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, conditional_z && res == 0, gb);
+    flag::bool_set(Flag::C, val & 1 != 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+}
+
+pub fn inc_u8<S: CpuBus>(reg: Register, gb: &mut S) {
+    let reg_val = read_register(reg, gb);
     let res = reg_val.wrapping_add(1);
-    write_register(reg, res, cpu);
-    flag::bool_set(Flag::Z, res == 0, cpu);
-    flag::bool_set(Flag::H, (res & 0x0F) == 0, cpu);
-    flag::reset(Flag::N, cpu);
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, res == 0, gb);
+    flag::bool_set(Flag::H, (res & 0x0F) == 0, gb);
+    flag::reset(Flag::N, gb);
 }
 
-pub fn inc_u16(reg: Register, cpu: &mut Cpu) {
-    write_multi_register(reg, read_multi_register(reg, cpu).wrapping_add(1), cpu);
+pub fn inc_u16<S: CpuBus>(reg: Register, gb: &mut S) {
+    write_multi_register(reg, read_multi_register(reg, gb).wrapping_add(1), gb);
 }
 
-pub fn dec_u8(reg: Register, cpu: &mut Cpu) {
-    let reg_val = read_register(reg, cpu);
+pub fn dec_u8<S: CpuBus>(reg: Register, gb: &mut S) {
+    let reg_val = read_register(reg, gb);
     let res = reg_val.wrapping_sub(1);
-    write_register(reg, res, cpu);
-    flag::bool_set(Flag::Z, res == 0, cpu);
-    flag::bool_set(Flag::H, (reg_val & 0x0F) == 0, cpu);
-    flag::set(Flag::N, cpu);
+    write_register(reg, res, gb);
+    flag::bool_set(Flag::Z, res == 0, gb);
+    flag::bool_set(Flag::H, (reg_val & 0x0F) == 0, gb);
+    flag::set(Flag::N, gb);
 }
 
-pub fn dec_u16(reg: Register, cpu: &mut Cpu) {
-    write_multi_register(reg, read_multi_register(reg, cpu).wrapping_sub(1), cpu);
+pub fn dec_u16<S: CpuBus>(reg: Register, gb: &mut S) {
+    write_multi_register(reg, read_multi_register(reg, gb).wrapping_sub(1), gb);
 }
 
-pub fn ccf(cpu: &mut Cpu) {
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-    flag::bool_set(Flag::C, !flag::is_set(Flag::C, cpu), cpu);
+pub fn ccf<S: CpuBus>(gb: &mut S) {
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+    flag::bool_set(Flag::C, !flag::is_set(Flag::C, gb), gb);
 }
 
-pub fn scf(cpu: &mut Cpu) {
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-    flag::set(Flag::C, cpu);
+pub fn scf<S: CpuBus>(gb: &mut S) {
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+    flag::set(Flag::C, gb);
 }
 
-pub fn cpl(cpu: &mut Cpu) {
-    write_register(Register::A, 0xFF - read_register(Register::A, cpu), cpu);
-    flag::set(Flag::N, cpu);
-    flag::set(Flag::H, cpu);
+pub fn cpl<S: CpuBus>(gb: &mut S) {
+    write_register(Register::A, 0xFF - read_register(Register::A, gb), gb);
+    flag::set(Flag::N, gb);
+    flag::set(Flag::H, gb);
 }
 
-fn swap(reg: Register, cpu: &mut Cpu) {
-    let reg_val = read_register(reg, cpu);
-    write_register(reg, ((reg_val & 0x0F) << 4) | ((reg_val & 0xF0) >> 4), cpu);
-    flag::bool_set(Flag::Z, reg_val == 0, cpu);
-    flag::reset(Flag::N, cpu);
-    flag::reset(Flag::H, cpu);
-    flag::reset(Flag::C, cpu);
+fn swap<S: CpuBus>(reg: Register, gb: &mut S) {
+    let reg_val = read_register(reg, gb);
+    write_register(reg, ((reg_val & 0x0F) << 4) | ((reg_val & 0xF0) >> 4), gb);
+    flag::bool_set(Flag::Z, reg_val == 0, gb);
+    flag::reset(Flag::N, gb);
+    flag::reset(Flag::H, gb);
+    flag::reset(Flag::C, gb);
 }
 
-fn cycle_offset(op: OpCode, cpu: &mut Cpu) -> usize {
-    use opcode::OpCode::*;
+// Every push (PUSH, CALL, RST) spends an internal cycle before writing, then
+// writes the high byte first
+pub fn stack_push<S: CpuBus>(val: u16, gb: &mut S) -> () {
+    let (l_byte, r_byte) = ((val >> 8) as u8, (0x00FF & val) as u8);
+    gb.tick(4);
+    gb.cpu().sp = gb.cpu().sp.wrapping_sub(1);
+    let sp = gb.cpu().sp;
+    gb.write(sp as usize, l_byte);
+    gb.cpu().sp = gb.cpu().sp.wrapping_sub(1);
+    let sp = gb.cpu().sp;
+    gb.write(sp as usize, r_byte);
+}
 
-    match op {
-        JP_C(cond, _) | JR_C(cond, _) => if test_cond(cond, cpu) { 4 } else { 0 },
-        CALL_C(cond, _) | RET_C(cond) => if test_cond(cond, cpu) { 12 } else { 0 },
-        _ => 0,
-    }
+pub fn stack_pop<S: CpuBus>(gb: &mut S) -> u16 {
+    let sp = gb.cpu().sp;
+    let r_byte = gb.read(sp as usize) as u16;
+    let l_byte = gb.read(sp.wrapping_add(1) as usize) as u16;
+    gb.cpu().sp = gb.cpu().sp.wrapping_add(2);
+    let res = (l_byte << 8) + r_byte;
+    res
 }
 
-pub fn exec_instr(op: OpCode, curr_addr: usize, cpu: &mut Cpu) -> (usize, usize) {
-    use opcode::OpCode::*;
-    use self::Register::*;
+// With IME off and an interrupt already pending HALT doesn't halt at all,
+// instead the next opcode fetch fails to increment PC (the HALT bug)
+pub fn halt<S: CpuBus>(gb: &mut S) {
+    if !gb.cpu().interrupt_master_enabled && interrupt::is_pending(gb) {
+        gb.cpu().halt_bug = true;
+    } else {
+        gb.cpu().halted = true;
+    }
+}
 
-    let offset = cycle_offset(op, cpu);
-    let new_addr = match op {
-        DAA => daa(curr_addr, cpu),
-        DI => {
-            cpu.interrupt_master_enabled = false;
-            curr_addr
-        }
-        EI => {
-            cpu.interrupt_master_enabled = true;
-            curr_addr
-        }
-        RES(pos, reg) => {
-            let val = read_register(reg, cpu);
-            let new_val = val & (0xFF - (1 << pos));
-            write_register(reg, new_val, cpu);
-            curr_addr
-        }
-        SET(pos, reg) => {
-            write_register(reg, read_register(reg, cpu) | (1 << pos), cpu);
-            let val = read_register(reg, cpu);
-            let new_val = val | (1 << pos);
-            write_register(reg, new_val, cpu);
-            curr_addr
-        }
-        JP(addr) => addr as usize,
-        JP_C(cond, reg_addr) => {
-            if test_cond(cond, cpu) {
-                reg_addr as usize
-            } else {
-                curr_addr
-            }
-        }
-        JP_HL => read_multi_register(Register::HL, cpu) as usize,
-        NOP => curr_addr,
-        XOR(reg) => {
-            xor_d8(read_register(reg, cpu), cpu);
-            curr_addr
-        }
-        XOR_d8(num) => {
-            xor_d8(num, cpu);
-            curr_addr
-        }
-        LD(reg, val) => ld(reg, val, curr_addr, cpu),
-        LD_M(reg, val) => ld_m(reg, val, curr_addr, cpu),
-        LDHL_SP(val) => ldhl_sp(val, curr_addr, cpu),
-        LD_SP_HL => {
-            cpu.sp = read_multi_register(Register::HL, cpu);
-            curr_addr
-        }
-        LD_ADDR_SP(addr) => {
-            let sp = cpu.sp;
-            safe_write_address(addr as usize, (sp & 0xFF) as u8, cpu);
-            safe_write_address(addr.wrapping_add(1) as usize, (sp >> 8) as u8, cpu);
-            curr_addr
-        }
-        LD_R(to_reg, from_reg) => ld_r(to_reg, from_reg, curr_addr, cpu),
-        BIT(bit_pos, reg) => {
-            bit(bit_pos, reg, cpu);
-            curr_addr
-        }
-        JR(offset) => wrapping_off_u16_i8(curr_addr as u16, offset) as usize,
-        JR_C(cond, offset) => {
-            if test_cond(cond, cpu) {
-                wrapping_off_u16_i8(curr_addr as u16, offset) as usize
-            } else {
-                curr_addr
-            }
-        }
-        DEC_F(reg) => {
-            dec_u8(reg, cpu);
-            curr_addr
-        }
-        DEC(reg) => {
-            dec_u16(reg, cpu);
-            curr_addr
-        }
-        INC_F(reg) => {
-            inc_u8(reg, cpu);
-            curr_addr
-        }
-        INC(reg) => {
-            inc_u16(reg, cpu);
-            curr_addr
-        }
-        CALL(reg_addr) | RST(reg_addr) => {
-            stack_push(curr_addr as u16, cpu);
-            reg_addr as usize
-        }
-        CALL_C(cond, reg_addr) => {
-            if test_cond(cond, cpu) {
-                stack_push(curr_addr as u16, cpu);
-                reg_addr as usize
-            } else {
-                curr_addr
-            }
-        }
-        PUSH(reg) => {
-            let val = read_multi_register(reg, cpu);
-            stack_push(val, cpu);
-            curr_addr
-        }
-        POP(reg) => {
-            let val = stack_pop(cpu);
-            write_multi_register(reg, val, cpu);
-            curr_addr
-        }
-        RL(reg) => {
-            rl(reg, true, cpu);
-            curr_addr
-        }
-        RLC(reg) => {
-            rlc(reg, true, cpu);
-            curr_addr
-        }
-        RLA => {
-            rl(Register::A, false, cpu);
-            curr_addr
-        }
-        RLCA => {
-            rlc(Register::A, false, cpu);
-            curr_addr
-        }
-        RR(reg) => {
-            rr(reg, true, cpu);
-            curr_addr
-        }
-        RRC(reg) => {
-            rrc(reg, true, cpu);
-            curr_addr
-        }
-        RRA => {
-            rr(Register::A, false, cpu);
-            curr_addr
-        }
-        RRCA => {
-            rrc(Register::A, false, cpu);
-            curr_addr
-        }
-        SRA(reg) => {
-            sra(reg, cpu);
-            curr_addr
-        }
-        SLA(reg) => {
-            sla(reg, cpu);
+// STOP's length and effect depend on whether a button is held and whether
+// an interrupt is pending, see "Using the STOP instruction" in pandocs
+pub fn stop<S: CpuBus>(curr_addr: usize, gb: &mut S) -> usize {
+    let pending = interrupt::is_pending(gb);
+    if gb.joypad_line_low() {
+        if pending {
+            // Nothing happens and STOP is only 1 byte
+            curr_addr - 1
+        } else {
+            // Becomes a 2 byte HALT
+            gb.cpu().halted = true;
             curr_addr
         }
-        SRL(reg) => {
-            srl(reg, cpu);
-            curr_addr
-        }
-        RET => stack_pop(cpu) as usize,
-        RETI => {
-            cpu.interrupt_master_enabled = true;
-            stack_pop(cpu) as usize
-        }
-        RET_C(cond) => {
-            if test_cond(cond, cpu) {
-                stack_pop(cpu) as usize
-            } else {
-                curr_addr
-            }
-        }
-        CP(reg) => {
-            cp(read_register(reg, cpu), cpu);
-            curr_addr
-        }
-        CP_d8(num) => {
-            cp(num, cpu);
-            curr_addr
-        }
-        SCF => {
-            scf(cpu);
-            curr_addr
-        }
-        CCF => {
-            ccf(cpu);
-            curr_addr
-        }
-        CPL => {
-            cpl(cpu);
-            curr_addr
-        }
-        SWAP(reg) => {
-            swap(reg, cpu);
-            curr_addr
-        }
-        AND(reg) => a_and_val(read_register(reg, cpu), curr_addr, cpu),
-        AND_d8(num) => a_and_val(num, curr_addr, cpu),
-        OR(reg) => a_or_val(read_register(reg, cpu), curr_addr, cpu),
-        OR_d8(num) => a_or_val(num, curr_addr, cpu),
-        ADD_r8(SP, offset) => add_to_sp(offset, curr_addr, cpu),
-        ADD_d8(A, num) => add_to_a(num, curr_addr, cpu),
-        ADD(HL, reg) => add_to_hl(read_multi_register(reg, cpu), curr_addr, cpu),
-        ADD(A, reg) => add_to_a(read_register(reg, cpu), curr_addr, cpu),
-        ADD_C(A, reg) => addc_to_a(read_register(reg, cpu), curr_addr, cpu),
-        ADD_C_d8(A, num) => addc_to_a(num, curr_addr, cpu),
-        SUB(reg) => sub_from_a(read_register(reg, cpu), curr_addr, cpu),
-        SUB_d8(num) => sub_from_a(num, curr_addr, cpu),
-        SUB_C(A, reg) => subc_from_a(read_register(reg, cpu), curr_addr, cpu),
-        SUB_C_d8(A, num) => subc_from_a(num, curr_addr, cpu),
-        _ => {
-            println!("Please implement {:?}", op);
-            unreachable!()
-        }
+    } else {
+        gb.cpu().stopped = true;
+        gb.reset_div();
+        cond!(pending, curr_addr - 1, curr_addr)
+    }
+}
+
+// Illegal opcodes hang the gb for good, only the rest of the system keeps going
+pub fn lock_up<S: CpuBus>(opcode: u8, curr_addr: usize, gb: &mut S) -> usize {
+    let address = curr_addr - 1;
+    let bank = gb.rom_bank_at(address);
+    gb.cpu().lockup = Some(Lockup {
+        address: address,
+        opcode: opcode,
+        bank: bank,
+    });
+    address
+}
+
+pub fn ld_addr_sp<S: CpuBus>(addr: u16, curr_addr: usize, gb: &mut S) -> usize {
+    let sp = gb.cpu().sp;
+    gb.write(addr as usize, (sp & 0xFF) as u8);
+    gb.write(addr.wrapping_add(1) as usize, (sp >> 8) as u8);
+    curr_addr
+}
+
+pub fn ld_sp_hl<S: CpuBus>(gb: &mut S) {
+    gb.cpu().sp = read_multi_register(Register::HL, gb);
+}
+
+pub fn di<S: CpuBus>(gb: &mut S) {
+    gb.cpu().interrupt_master_enabled = false;
+    gb.cpu().ime_scheduled = false;
+}
+
+// Enabled once the next instruction has run
+pub fn ei<S: CpuBus>(gb: &mut S) {
+    gb.cpu().ime_scheduled = true;
+}
+
+pub fn push<S: CpuBus>(reg: Register, curr_addr: usize, gb: &mut S) -> usize {
+    let val = read_multi_register(reg, gb);
+    stack_push(val, gb);
+    curr_addr
+}
+
+pub fn pop<S: CpuBus>(reg: Register, curr_addr: usize, gb: &mut S) -> usize {
+    let val = stack_pop(gb);
+    write_multi_register(reg, val, gb);
+    curr_addr
+}
+
+pub fn jr(offset: i8, curr_addr: usize) -> usize {
+    wrapping_off_u16_i8(curr_addr as u16, offset) as usize
+}
+
+// A taken conditional jump spends an internal cycle loading PC
+pub fn jr_if<S: CpuBus>(cond: Cond, offset: i8, curr_addr: usize, gb: &mut S) -> usize {
+    if test_cond(cond, gb) {
+        gb.tick(4);
+        jr(offset, curr_addr)
+    } else {
+        curr_addr
+    }
+}
+
+pub fn jp_if<S: CpuBus>(cond: Cond, addr: u16, curr_addr: usize, gb: &mut S) -> usize {
+    if test_cond(cond, gb) {
+        gb.tick(4);
+        addr as usize
+    } else {
+        curr_addr
+    }
+}
+
+// Also RST, which is a 1 byte CALL to a fixed address
+pub fn call<S: CpuBus>(addr: u16, curr_addr: usize, gb: &mut S) -> usize {
+    stack_push(curr_addr as u16, gb);
+    addr as usize
+}
+
+pub fn call_if<S: CpuBus>(cond: Cond, addr: u16, curr_addr: usize, gb: &mut S) -> usize {
+    cond!(test_cond(cond, gb), call(addr, curr_addr, gb), curr_addr)
+}
+
+// Checking the condition takes an internal cycle, and so does loading PC
+// once it's popped
+pub fn ret_if<S: CpuBus>(cond: Cond, curr_addr: usize, gb: &mut S) -> usize {
+    if test_cond(cond, gb) {
+        gb.tick(4);
+        let addr = stack_pop(gb) as usize;
+        gb.tick(4);
+        addr
+    } else {
+        curr_addr
+    }
+}
+
+pub fn reti<S: CpuBus>(gb: &mut S) -> usize {
+    gb.cpu().interrupt_master_enabled = true;
+    stack_pop(gb) as usize
+}
+
+pub fn res<S: CpuBus>(pos: u8, reg: Register, gb: &mut S) {
+    let val = read_register(reg, gb);
+    write_register(reg, val & !(1 << pos), gb);
+}
+
+pub fn set<S: CpuBus>(pos: u8, reg: Register, gb: &mut S) {
+    let val = read_register(reg, gb);
+    write_register(reg, val | (1 << pos), gb);
+}
+
+// The byte after 0xCB picks the operation with its upper 5 bits, the bit
+// for BIT, RES and SET being the lower 3 of those, and the register with
+// its lower 3 bits
+fn prefix_cb<S: CpuBus>(b: u8, gb: &mut S) {
+    let reg = lookup_mod_register(b);
+    Handlers::<S>::CB_OPS[(b >> 3) as usize]((b >> 3) & 7, reg, gb)
+}
+
+// Runs `instr`, `curr_addr` being the address right after it. Returns the
+// address of the next instruction.
+pub fn exec<S: CpuBus>(instr: Instr, curr_addr: usize, gb: &mut S) -> usize {
+    Handlers::<S>::OPS[instr.opcode as usize](instr.arg, curr_addr, gb)
+}
+
+// Every handler gets the instruction's argument and the address after it,
+// and returns the address of the next instruction
+type Handler<S> = fn(u16, usize, &mut S) -> usize;
+// CB handlers get the bit and the register picked by the second byte
+type CbHandler<S> = fn(u8, Register, &mut S);
+
+// Handlers are generic over the bus, so the tables are built for every bus
+// type the cpu runs on
+struct Handlers<S>(PhantomData<S>);
+
+impl<S: CpuBus> Handlers<S> {
+    // Handlers indexed by opcode
+    const OPS: [Handler<S>; 256] = {
+        use register::Register::*;
+        [
+        /* 0x00 */ |_, addr, _| addr,
+        /* 0x01 */ |arg, addr, gb| ld_m(BC, arg, addr, gb),
+        /* 0x02 */ |_, addr, gb| ld_r(BC_ADDR, A, addr, gb),
+        /* 0x03 */ |_, addr, gb| { inc_u16(BC, gb); addr },
+        /* 0x04 */ |_, addr, gb| { inc_u8(B, gb); addr },
+        /* 0x05 */ |_, addr, gb| { dec_u8(B, gb); addr },
+        /* 0x06 */ |arg, addr, gb| ld(B, arg as u8, addr, gb),
+        /* 0x07 */ |_, addr, gb| { rlc(A, false, gb); addr },
+        /* 0x08 */ |arg, addr, gb| ld_addr_sp(arg, addr, gb),
+        /* 0x09 */ |_, addr, gb| add_to_hl(read_multi_register(BC, gb), addr, gb),
+        /* 0x0A */ |_, addr, gb| ld_r(A, BC_ADDR, addr, gb),
+        /* 0x0B */ |_, addr, gb| { dec_u16(BC, gb); addr },
+        /* 0x0C */ |_, addr, gb| { inc_u8(C, gb); addr },
+        /* 0x0D */ |_, addr, gb| { dec_u8(C, gb); addr },
+        /* 0x0E */ |arg, addr, gb| ld(C, arg as u8, addr, gb),
+        /* 0x0F */ |_, addr, gb| { rrc(A, false, gb); addr },
+        /* 0x10 */ |_, addr, gb| stop(addr, gb),
+        /* 0x11 */ |arg, addr, gb| ld_m(DE, arg, addr, gb),
+        /* 0x12 */ |_, addr, gb| ld_r(DE_ADDR, A, addr, gb),
+        /* 0x13 */ |_, addr, gb| { inc_u16(DE, gb); addr },
+        /* 0x14 */ |_, addr, gb| { inc_u8(D, gb); addr },
+        /* 0x15 */ |_, addr, gb| { dec_u8(D, gb); addr },
+        /* 0x16 */ |arg, addr, gb| ld(D, arg as u8, addr, gb),
+        /* 0x17 */ |_, addr, gb| { rl(A, false, gb); addr },
+        /* 0x18 */ |arg, addr, _| jr(arg as i8, addr),
+        /* 0x19 */ |_, addr, gb| add_to_hl(read_multi_register(DE, gb), addr, gb),
+        /* 0x1A */ |_, addr, gb| ld_r(A, DE_ADDR, addr, gb),
+        /* 0x1B */ |_, addr, gb| { dec_u16(DE, gb); addr },
+        /* 0x1C */ |_, addr, gb| { inc_u8(E, gb); addr },
+        /* 0x1D */ |_, addr, gb| { dec_u8(E, gb); addr },
+        /* 0x1E */ |arg, addr, gb| ld(E, arg as u8, addr, gb),
+        /* 0x1F */ |_, addr, gb| { rr(A, false, gb); addr },
+        /* 0x20 */ |arg, addr, gb| jr_if(Cond::NZ, arg as i8, addr, gb),
+        /* 0x21 */ |arg, addr, gb| ld_m(HL, arg, addr, gb),
+        /* 0x22 */ |_, addr, gb| ld_r(HLP, A, addr, gb),
+        /* 0x23 */ |_, addr, gb| { inc_u16(HL, gb); addr },
+        /* 0x24 */ |_, addr, gb| { inc_u8(H, gb); addr },
+        /* 0x25 */ |_, addr, gb| { dec_u8(H, gb); addr },
+        /* 0x26 */ |arg, addr, gb| ld(H, arg as u8, addr, gb),
+        /* 0x27 */ |_, addr, gb| daa(addr, gb),
+        /* 0x28 */ |arg, addr, gb| jr_if(Cond::Z, arg as i8, addr, gb),
+        /* 0x29 */ |_, addr, gb| add_to_hl(read_multi_register(HL, gb), addr, gb),
+        /* 0x2A */ |_, addr, gb| ld_r(A, HLP, addr, gb),
+        /* 0x2B */ |_, addr, gb| { dec_u16(HL, gb); addr },
+        /* 0x2C */ |_, addr, gb| { inc_u8(L, gb); addr },
+        /* 0x2D */ |_, addr, gb| { dec_u8(L, gb); addr },
+        /* 0x2E */ |arg, addr, gb| ld(L, arg as u8, addr, gb),
+        /* 0x2F */ |_, addr, gb| { cpl(gb); addr },
+        /* 0x30 */ |arg, addr, gb| jr_if(Cond::NC, arg as i8, addr, gb),
+        /* 0x31 */ |arg, addr, gb| ld_m(SP, arg, addr, gb),
+        /* 0x32 */ |_, addr, gb| ld_r(HLM, A, addr, gb),
+        /* 0x33 */ |_, addr, gb| { inc_u16(SP, gb); addr },
+        /* 0x34 */ |_, addr, gb| { inc_u8(HL_ADDR, gb); addr },
+        /* 0x35 */ |_, addr, gb| { dec_u8(HL_ADDR, gb); addr },
+        /* 0x36 */ |arg, addr, gb| ld(HL_ADDR, arg as u8, addr, gb),
+        /* 0x37 */ |_, addr, gb| { scf(gb); addr },
+        /* 0x38 */ |arg, addr, gb| jr_if(Cond::C, arg as i8, addr, gb),
+        /* 0x39 */ |_, addr, gb| add_to_hl(read_multi_register(SP, gb), addr, gb),
+        /* 0x3A */ |_, addr, gb| ld_r(A, HLM, addr, gb),
+        /* 0x3B */ |_, addr, gb| { dec_u16(SP, gb); addr },
+        /* 0x3C */ |_, addr, gb| { inc_u8(A, gb); addr },
+        /* 0x3D */ |_, addr, gb| { dec_u8(A, gb); addr },
+        /* 0x3E */ |arg, addr, gb| ld(A, arg as u8, addr, gb),
+        /* 0x3F */ |_, addr, gb| { ccf(gb); addr },
+        /* 0x40 */ |_, addr, gb| ld_r(B, B, addr, gb),
+        /* 0x41 */ |_, addr, gb| ld_r(B, C, addr, gb),
+        /* 0x42 */ |_, addr, gb| ld_r(B, D, addr, gb),
+        /* 0x43 */ |_, addr, gb| ld_r(B, E, addr, gb),
+        /* 0x44 */ |_, addr, gb| ld_r(B, H, addr, gb),
+        /* 0x45 */ |_, addr, gb| ld_r(B, L, addr, gb),
+        /* 0x46 */ |_, addr, gb| ld_r(B, HL_ADDR, addr, gb),
+        /* 0x47 */ |_, addr, gb| ld_r(B, A, addr, gb),
+        /* 0x48 */ |_, addr, gb| ld_r(C, B, addr, gb),
+        /* 0x49 */ |_, addr, gb| ld_r(C, C, addr, gb),
+        /* 0x4A */ |_, addr, gb| ld_r(C, D, addr, gb),
+        /* 0x4B */ |_, addr, gb| ld_r(C, E, addr, gb),
+        /* 0x4C */ |_, addr, gb| ld_r(C, H, addr, gb),
+        /* 0x4D */ |_, addr, gb| ld_r(C, L, addr, gb),
+        /* 0x4E */ |_, addr, gb| ld_r(C, HL_ADDR, addr, gb),
+        /* 0x4F */ |_, addr, gb| ld_r(C, A, addr, gb),
+        /* 0x50 */ |_, addr, gb| ld_r(D, B, addr, gb),
+        /* 0x51 */ |_, addr, gb| ld_r(D, C, addr, gb),
+        /* 0x52 */ |_, addr, gb| ld_r(D, D, addr, gb),
+        /* 0x53 */ |_, addr, gb| ld_r(D, E, addr, gb),
+        /* 0x54 */ |_, addr, gb| ld_r(D, H, addr, gb),
+        /* 0x55 */ |_, addr, gb| ld_r(D, L, addr, gb),
+        /* 0x56 */ |_, addr, gb| ld_r(D, HL_ADDR, addr, gb),
+        /* 0x57 */ |_, addr, gb| ld_r(D, A, addr, gb),
+        /* 0x58 */ |_, addr, gb| ld_r(E, B, addr, gb),
+        /* 0x59 */ |_, addr, gb| ld_r(E, C, addr, gb),
+        /* 0x5A */ |_, addr, gb| ld_r(E, D, addr, gb),
+        /* 0x5B */ |_, addr, gb| ld_r(E, E, addr, gb),
+        /* 0x5C */ |_, addr, gb| ld_r(E, H, addr, gb),
+        /* 0x5D */ |_, addr, gb| ld_r(E, L, addr, gb),
+        /* 0x5E */ |_, addr, gb| ld_r(E, HL_ADDR, addr, gb),
+        /* 0x5F */ |_, addr, gb| ld_r(E, A, addr, gb),
+        /* 0x60 */ |_, addr, gb| ld_r(H, B, addr, gb),
+        /* 0x61 */ |_, addr, gb| ld_r(H, C, addr, gb),
+        /* 0x62 */ |_, addr, gb| ld_r(H, D, addr, gb),
+        /* 0x63 */ |_, addr, gb| ld_r(H, E, addr, gb),
+        /* 0x64 */ |_, addr, gb| ld_r(H, H, addr, gb),
+        /* 0x65 */ |_, addr, gb| ld_r(H, L, addr, gb),
+        /* 0x66 */ |_, addr, gb| ld_r(H, HL_ADDR, addr, gb),
+        /* 0x67 */ |_, addr, gb| ld_r(H, A, addr, gb),
+        /* 0x68 */ |_, addr, gb| ld_r(L, B, addr, gb),
+        /* 0x69 */ |_, addr, gb| ld_r(L, C, addr, gb),
+        /* 0x6A */ |_, addr, gb| ld_r(L, D, addr, gb),
+        /* 0x6B */ |_, addr, gb| ld_r(L, E, addr, gb),
+        /* 0x6C */ |_, addr, gb| ld_r(L, H, addr, gb),
+        /* 0x6D */ |_, addr, gb| ld_r(L, L, addr, gb),
+        /* 0x6E */ |_, addr, gb| ld_r(L, HL_ADDR, addr, gb),
+        /* 0x6F */ |_, addr, gb| ld_r(L, A, addr, gb),
+        /* 0x70 */ |_, addr, gb| ld_r(HL_ADDR, B, addr, gb),
+        /* 0x71 */ |_, addr, gb| ld_r(HL_ADDR, C, addr, gb),
+        /* 0x72 */ |_, addr, gb| ld_r(HL_ADDR, D, addr, gb),
+        /* 0x73 */ |_, addr, gb| ld_r(HL_ADDR, E, addr, gb),
+        /* 0x74 */ |_, addr, gb| ld_r(HL_ADDR, H, addr, gb),
+        /* 0x75 */ |_, addr, gb| ld_r(HL_ADDR, L, addr, gb),
+        /* 0x76 */ |_, addr, gb| { halt(gb); addr },
+        /* 0x77 */ |_, addr, gb| ld_r(HL_ADDR, A, addr, gb),
+        /* 0x78 */ |_, addr, gb| ld_r(A, B, addr, gb),
+        /* 0x79 */ |_, addr, gb| ld_r(A, C, addr, gb),
+        /* 0x7A */ |_, addr, gb| ld_r(A, D, addr, gb),
+        /* 0x7B */ |_, addr, gb| ld_r(A, E, addr, gb),
+        /* 0x7C */ |_, addr, gb| ld_r(A, H, addr, gb),
+        /* 0x7D */ |_, addr, gb| ld_r(A, L, addr, gb),
+        /* 0x7E */ |_, addr, gb| ld_r(A, HL_ADDR, addr, gb),
+        /* 0x7F */ |_, addr, gb| ld_r(A, A, addr, gb),
+        /* 0x80 */ |_, addr, gb| add_to_a(read_register(B, gb), addr, gb),
+        /* 0x81 */ |_, addr, gb| add_to_a(read_register(C, gb), addr, gb),
+        /* 0x82 */ |_, addr, gb| add_to_a(read_register(D, gb), addr, gb),
+        /* 0x83 */ |_, addr, gb| add_to_a(read_register(E, gb), addr, gb),
+        /* 0x84 */ |_, addr, gb| add_to_a(read_register(H, gb), addr, gb),
+        /* 0x85 */ |_, addr, gb| add_to_a(read_register(L, gb), addr, gb),
+        /* 0x86 */ |_, addr, gb| add_to_a(read_register(HL_ADDR, gb), addr, gb),
+        /* 0x87 */ |_, addr, gb| add_to_a(read_register(A, gb), addr, gb),
+        /* 0x88 */ |_, addr, gb| addc_to_a(read_register(B, gb), addr, gb),
+        /* 0x89 */ |_, addr, gb| addc_to_a(read_register(C, gb), addr, gb),
+        /* 0x8A */ |_, addr, gb| addc_to_a(read_register(D, gb), addr, gb),
+        /* 0x8B */ |_, addr, gb| addc_to_a(read_register(E, gb), addr, gb),
+        /* 0x8C */ |_, addr, gb| addc_to_a(read_register(H, gb), addr, gb),
+        /* 0x8D */ |_, addr, gb| addc_to_a(read_register(L, gb), addr, gb),
+        /* 0x8E */ |_, addr, gb| addc_to_a(read_register(HL_ADDR, gb), addr, gb),
+        /* 0x8F */ |_, addr, gb| addc_to_a(read_register(A, gb), addr, gb),
+        /* 0x90 */ |_, addr, gb| sub_from_a(read_register(B, gb), addr, gb),
+        /* 0x91 */ |_, addr, gb| sub_from_a(read_register(C, gb), addr, gb),
+        /* 0x92 */ |_, addr, gb| sub_from_a(read_register(D, gb), addr, gb),
+        /* 0x93 */ |_, addr, gb| sub_from_a(read_register(E, gb), addr, gb),
+        /* 0x94 */ |_, addr, gb| sub_from_a(read_register(H, gb), addr, gb),
+        /* 0x95 */ |_, addr, gb| sub_from_a(read_register(L, gb), addr, gb),
+        /* 0x96 */ |_, addr, gb| sub_from_a(read_register(HL_ADDR, gb), addr, gb),
+        /* 0x97 */ |_, addr, gb| sub_from_a(read_register(A, gb), addr, gb),
+        /* 0x98 */ |_, addr, gb| subc_from_a(read_register(B, gb), addr, gb),
+        /* 0x99 */ |_, addr, gb| subc_from_a(read_register(C, gb), addr, gb),
+        /* 0x9A */ |_, addr, gb| subc_from_a(read_register(D, gb), addr, gb),
+        /* 0x9B */ |_, addr, gb| subc_from_a(read_register(E, gb), addr, gb),
+        /* 0x9C */ |_, addr, gb| subc_from_a(read_register(H, gb), addr, gb),
+        /* 0x9D */ |_, addr, gb| subc_from_a(read_register(L, gb), addr, gb),
+        /* 0x9E */ |_, addr, gb| subc_from_a(read_register(HL_ADDR, gb), addr, gb),
+        /* 0x9F */ |_, addr, gb| subc_from_a(read_register(A, gb), addr, gb),
+        /* 0xA0 */ |_, addr, gb| a_and_val(read_register(B, gb), addr, gb),
+        /* 0xA1 */ |_, addr, gb| a_and_val(read_register(C, gb), addr, gb),
+        /* 0xA2 */ |_, addr, gb| a_and_val(read_register(D, gb), addr, gb),
+        /* 0xA3 */ |_, addr, gb| a_and_val(read_register(E, gb), addr, gb),
+        /* 0xA4 */ |_, addr, gb| a_and_val(read_register(H, gb), addr, gb),
+        /* 0xA5 */ |_, addr, gb| a_and_val(read_register(L, gb), addr, gb),
+        /* 0xA6 */ |_, addr, gb| a_and_val(read_register(HL_ADDR, gb), addr, gb),
+        /* 0xA7 */ |_, addr, gb| a_and_val(read_register(A, gb), addr, gb),
+        /* 0xA8 */ |_, addr, gb| { xor_d8(read_register(B, gb), gb); addr },
+        /* 0xA9 */ |_, addr, gb| { xor_d8(read_register(C, gb), gb); addr },
+        /* 0xAA */ |_, addr, gb| { xor_d8(read_register(D, gb), gb); addr },
+        /* 0xAB */ |_, addr, gb| { xor_d8(read_register(E, gb), gb); addr },
+        /* 0xAC */ |_, addr, gb| { xor_d8(read_register(H, gb), gb); addr },
+        /* 0xAD */ |_, addr, gb| { xor_d8(read_register(L, gb), gb); addr },
+        /* 0xAE */ |_, addr, gb| { xor_d8(read_register(HL_ADDR, gb), gb); addr },
+        /* 0xAF */ |_, addr, gb| { xor_d8(read_register(A, gb), gb); addr },
+        /* 0xB0 */ |_, addr, gb| a_or_val(read_register(B, gb), addr, gb),
+        /* 0xB1 */ |_, addr, gb| a_or_val(read_register(C, gb), addr, gb),
+        /* 0xB2 */ |_, addr, gb| a_or_val(read_register(D, gb), addr, gb),
+        /* 0xB3 */ |_, addr, gb| a_or_val(read_register(E, gb), addr, gb),
+        /* 0xB4 */ |_, addr, gb| a_or_val(read_register(H, gb), addr, gb),
+        /* 0xB5 */ |_, addr, gb| a_or_val(read_register(L, gb), addr, gb),
+        /* 0xB6 */ |_, addr, gb| a_or_val(read_register(HL_ADDR, gb), addr, gb),
+        /* 0xB7 */ |_, addr, gb| a_or_val(read_register(A, gb), addr, gb),
+        /* 0xB8 */ |_, addr, gb| { cp(read_register(B, gb), gb); addr },
+        /* 0xB9 */ |_, addr, gb| { cp(read_register(C, gb), gb); addr },
+        /* 0xBA */ |_, addr, gb| { cp(read_register(D, gb), gb); addr },
+        /* 0xBB */ |_, addr, gb| { cp(read_register(E, gb), gb); addr },
+        /* 0xBC */ |_, addr, gb| { cp(read_register(H, gb), gb); addr },
+        /* 0xBD */ |_, addr, gb| { cp(read_register(L, gb), gb); addr },
+        /* 0xBE */ |_, addr, gb| { cp(read_register(HL_ADDR, gb), gb); addr },
+        /* 0xBF */ |_, addr, gb| { cp(read_register(A, gb), gb); addr },
+        /* 0xC0 */ |_, addr, gb| ret_if(Cond::NZ, addr, gb),
+        /* 0xC1 */ |_, addr, gb| pop(BC, addr, gb),
+        /* 0xC2 */ |arg, addr, gb| jp_if(Cond::NZ, arg, addr, gb),
+        /* 0xC3 */ |arg, _, _| arg as usize,
+        /* 0xC4 */ |arg, addr, gb| call_if(Cond::NZ, arg, addr, gb),
+        /* 0xC5 */ |_, addr, gb| push(BC, addr, gb),
+        /* 0xC6 */ |arg, addr, gb| add_to_a(arg as u8, addr, gb),
+        /* 0xC7 */ |_, addr, gb| call(0x00, addr, gb),
+        /* 0xC8 */ |_, addr, gb| ret_if(Cond::Z, addr, gb),
+        /* 0xC9 */ |_, _, gb| stack_pop(gb) as usize,
+        /* 0xCA */ |arg, addr, gb| jp_if(Cond::Z, arg, addr, gb),
+        /* 0xCB */ |arg, addr, gb| { prefix_cb(arg as u8, gb); addr },
+        /* 0xCC */ |arg, addr, gb| call_if(Cond::Z, arg, addr, gb),
+        /* 0xCD */ |arg, addr, gb| call(arg, addr, gb),
+        /* 0xCE */ |arg, addr, gb| addc_to_a(arg as u8, addr, gb),
+        /* 0xCF */ |_, addr, gb| call(0x08, addr, gb),
+        /* 0xD0 */ |_, addr, gb| ret_if(Cond::NC, addr, gb),
+        /* 0xD1 */ |_, addr, gb| pop(DE, addr, gb),
+        /* 0xD2 */ |arg, addr, gb| jp_if(Cond::NC, arg, addr, gb),
+        /* 0xD3 */ |_, addr, gb| lock_up(0xD3, addr, gb),
+        /* 0xD4 */ |arg, addr, gb| call_if(Cond::NC, arg, addr, gb),
+        /* 0xD5 */ |_, addr, gb| push(DE, addr, gb),
+        /* 0xD6 */ |arg, addr, gb| sub_from_a(arg as u8, addr, gb),
+        /* 0xD7 */ |_, addr, gb| call(0x10, addr, gb),
+        /* 0xD8 */ |_, addr, gb| ret_if(Cond::C, addr, gb),
+        /* 0xD9 */ |_, _, gb| reti(gb),
+        /* 0xDA */ |arg, addr, gb| jp_if(Cond::C, arg, addr, gb),
+        /* 0xDB */ |_, addr, gb| lock_up(0xDB, addr, gb),
+        /* 0xDC */ |arg, addr, gb| call_if(Cond::C, arg, addr, gb),
+        /* 0xDD */ |_, addr, gb| lock_up(0xDD, addr, gb),
+        /* 0xDE */ |arg, addr, gb| subc_from_a(arg as u8, addr, gb),
+        /* 0xDF */ |_, addr, gb| call(0x18, addr, gb),
+        /* 0xE0 */ |arg, addr, gb| ld_r(ADDR(0xFF00 + arg), A, addr, gb),
+        /* 0xE1 */ |_, addr, gb| pop(HL, addr, gb),
+        /* 0xE2 */ |_, addr, gb| ld_r(CH, A, addr, gb),
+        /* 0xE3 */ |_, addr, gb| lock_up(0xE3, addr, gb),
+        /* 0xE4 */ |_, addr, gb| lock_up(0xE4, addr, gb),
+        /* 0xE5 */ |_, addr, gb| push(HL, addr, gb),
+        /* 0xE6 */ |arg, addr, gb| a_and_val(arg as u8, addr, gb),
+        /* 0xE7 */ |_, addr, gb| call(0x20, addr, gb),
+        /* 0xE8 */ |arg, addr, gb| add_to_sp(arg as i8, addr, gb),
+        /* 0xE9 */ |_, _, gb| read_multi_register(HL, gb) as usize,
+        /* 0xEA */ |arg, addr, gb| ld_r(ADDR(arg), A, addr, gb),
+        /* 0xEB */ |_, addr, gb| lock_up(0xEB, addr, gb),
+        /* 0xEC */ |_, addr, gb| lock_up(0xEC, addr, gb),
+        /* 0xED */ |_, addr, gb| lock_up(0xED, addr, gb),
+        /* 0xEE */ |arg, addr, gb| { xor_d8(arg as u8, gb); addr },
+        /* 0xEF */ |_, addr, gb| call(0x28, addr, gb),
+        /* 0xF0 */ |arg, addr, gb| ld_r(A, ADDR(0xFF00 + arg), addr, gb),
+        /* 0xF1 */ |_, addr, gb| pop(AF, addr, gb),
+        /* 0xF2 */ |_, addr, gb| ld_r(A, CH, addr, gb),
+        /* 0xF3 */ |_, addr, gb| { di(gb); addr },
+        /* 0xF4 */ |_, addr, gb| lock_up(0xF4, addr, gb),
+        /* 0xF5 */ |_, addr, gb| push(AF, addr, gb),
+        /* 0xF6 */ |arg, addr, gb| a_or_val(arg as u8, addr, gb),
+        /* 0xF7 */ |_, addr, gb| call(0x30, addr, gb),
+        /* 0xF8 */ |arg, addr, gb| ldhl_sp(arg as i8, addr, gb),
+        /* 0xF9 */ |_, addr, gb| { ld_sp_hl(gb); addr },
+        /* 0xFA */ |arg, addr, gb| ld_r(A, ADDR(arg), addr, gb),
+        /* 0xFB */ |_, addr, gb| { ei(gb); addr },
+        /* 0xFC */ |_, addr, gb| lock_up(0xFC, addr, gb),
+        /* 0xFD */ |_, addr, gb| lock_up(0xFD, addr, gb),
+        /* 0xFE */ |arg, addr, gb| { cp(arg as u8, gb); addr },
+        /* 0xFF */ |_, addr, gb| call(0x38, addr, gb),
+        ]
     };
 
-    (offset, new_addr)
+    // CB handlers indexed by the upper 5 bits of the second byte
+    const CB_OPS: [CbHandler<S>; 32] = [
+        /* 0x00 */ |_, reg, gb| rlc(reg, true, gb),
+        /* 0x08 */ |_, reg, gb| rrc(reg, true, gb),
+        /* 0x10 */ |_, reg, gb| rl(reg, true, gb),
+        /* 0x18 */ |_, reg, gb| rr(reg, true, gb),
+        /* 0x20 */ |_, reg, gb| sla(reg, gb),
+        /* 0x28 */ |_, reg, gb| sra(reg, gb),
+        /* 0x30 */ |_, reg, gb| swap(reg, gb),
+        /* 0x38 */ |_, reg, gb| srl(reg, gb),
+        /* 0x40 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x48 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x50 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x58 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x60 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x68 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x70 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x78 */ |pos, reg, gb| bit(pos, reg, gb),
+        /* 0x80 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0x88 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0x90 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0x98 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0xA0 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0xA8 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0xB0 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0xB8 */ |pos, reg, gb| res(pos, reg, gb),
+        /* 0xC0 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xC8 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xD0 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xD8 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xE0 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xE8 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xF0 */ |pos, reg, gb| set(pos, reg, gb),
+        /* 0xF8 */ |pos, reg, gb| set(pos, reg, gb),
+    ];
 }
@@ -1,69 +1,125 @@
-use cpu::*;
+use sm83::CpuBus;
 use self::Interrupt::*;
 
-// Ordered in descending priority
-#[derive(Debug, Clone, Copy)]
+// Ordered in descending priority, which is also the order of their bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Interrupt {
     VBlank,
     LCD,
     Timer,
-    Joypad,
     Serial,
+    Joypad,
+}
+
+pub const PRIORITY: [Interrupt; 5] = [VBlank, LCD, Timer, Serial, Joypad];
+
+// Only the low 5 bits of IF exist, the rest always read back as 1
+pub const IF_MASK: u8 = 0x1F;
+
+pub fn read_if<S: CpuBus>(gb: &S) -> u8 {
+    gb.interrupt_flag() | !IF_MASK
+}
+
+pub fn write_if<S: CpuBus>(val: u8, gb: &mut S) {
+    gb.set_interrupt_flag(val & IF_MASK);
+}
+
+// IE is a plain 8-bit register, the upper bits are kept but never match an
+// interrupt
+pub fn read_ie<S: CpuBus>(gb: &S) -> u8 {
+    gb.interrupt_enable()
+}
+
+pub fn write_ie<S: CpuBus>(val: u8, gb: &mut S) {
+    gb.set_interrupt_enable(val);
 }
 
-pub fn exec_halt_interrupts(address: usize, cpu: &mut Cpu) -> usize {
-    let interrupts = [VBlank, LCD, Timer, Serial, Joypad];
-    interrupts.iter()
-        .find(|&interrupt| interrupt.is_requested(cpu) && interrupt.is_enabled(cpu))
-        .map_or(address, |interrupt| if cpu.interrupt_master_enabled {
-            cpu.halted = false;
-            interrupt.exec(address + 1, cpu)
-        } else {
-            cpu.halted = false;
-            address + 1
-        })
+// Whether any enabled interrupt is requested, regardless of IME
+pub fn is_pending<S: CpuBus>(gb: &S) -> bool {
+    pending(gb).is_some()
 }
 
-pub fn exec_interrupts(address: usize, cpu: &mut Cpu) -> usize {
-    if cpu.interrupt_master_enabled {
-        let interrupts = [VBlank, LCD, Timer, Serial, Joypad];
-        interrupts.iter()
-            .find(|&interrupt| interrupt.is_requested(cpu) && interrupt.is_enabled(cpu))
-            .map_or(address, |interrupt| interrupt.exec(address, cpu))
+// The highest priority interrupt that is both requested and enabled
+pub fn pending<S: CpuBus>(gb: &S) -> Option<Interrupt> {
+    PRIORITY.iter()
+        .find(|&interrupt| interrupt.is_requested(gb) && interrupt.is_enabled(gb))
+        .cloned()
+}
+
+// A pending interrupt ends HALT even with IME off, it's only serviced with IME on
+pub fn exec_halt_interrupts<S: CpuBus>(address: usize, gb: &mut S) -> Option<usize> {
+    if is_pending(gb) {
+        gb.cpu().halted = false;
+        exec_interrupts(address, gb)
     } else {
-        address
+        None
     }
 }
 
+// Checked between instructions, returns the new address if an interrupt was
+// dispatched. EI only takes effect after this check for the instruction that
+// follows it.
+pub fn exec_interrupts<S: CpuBus>(address: usize, gb: &mut S) -> Option<usize> {
+    let ime = gb.cpu().interrupt_master_enabled;
+    if gb.cpu().ime_scheduled {
+        gb.cpu().ime_scheduled = false;
+        gb.cpu().interrupt_master_enabled = true;
+    }
+    if ime && is_pending(gb) {
+        Some(dispatch(address, gb))
+    } else {
+        None
+    }
+}
+
+// Takes 5 M-cycles: two waits, pushing PC high, pushing PC low and the jump.
+// Which interrupt to jump to is only decided after the high byte is pushed, so
+// if that push lands on IE (SP = 0x0000) and clears the pending bit, the
+// dispatch is cancelled and execution continues at 0x0000 instead.
+fn dispatch<S: CpuBus>(address: usize, gb: &mut S) -> usize {
+    gb.cpu().interrupt_master_enabled = false;
+    gb.cpu().halted = false;
+    gb.tick(8);
+    gb.cpu().sp = gb.cpu().sp.wrapping_sub(1);
+    let sp = gb.cpu().sp as usize;
+    gb.write(sp, (address >> 8) as u8);
+    let interrupt = pending(gb);
+    gb.cpu().sp = gb.cpu().sp.wrapping_sub(1);
+    let sp = gb.cpu().sp as usize;
+    gb.write(sp, address as u8);
+    gb.tick(4);
+    match interrupt {
+        Some(interrupt) => {
+            interrupt.acknowledge(gb);
+            interrupt.interrupt_address()
+        }
+        None => 0x0000,
+    }
+}
 
 impl Interrupt {
-    pub fn request(&self, cpu: &mut Cpu) {
-        let requests = get_requests(cpu);
-        let mask = 1 << self.bit_pos();
-        write_address(0xFF0F, requests | mask, cpu);
+    pub fn request<S: CpuBus>(&self, gb: &mut S) {
+        let flag = gb.interrupt_flag() | self.mask();
+        gb.set_interrupt_flag(flag);
     }
 
-    fn exec(&self, address: usize, cpu: &mut Cpu) -> usize {
-        cpu.interrupt_master_enabled = false;
-        self.reset_request(cpu);
-        stack_push(address as u16, cpu);
-        self.interrupt_address()
+    // Clears the request, as the cpu does when it services the interrupt
+    pub fn acknowledge<S: CpuBus>(&self, gb: &mut S) {
+        let flag = gb.interrupt_flag() & !self.mask();
+        gb.set_interrupt_flag(flag);
     }
 
-    fn reset_request(&self, cpu: &mut Cpu) {
-        let requests = get_requests(cpu);
-        let mask = 0xFF - (1 << self.bit_pos());
-        write_address(0xFF0F, requests & mask, cpu);
+    pub fn is_requested<S: CpuBus>(&self, gb: &S) -> bool {
+        gb.interrupt_flag() & self.mask() != 0
     }
 
-    fn is_requested(&self, cpu: &mut Cpu) -> bool {
-        let val = read_address(0xFF0F, cpu) & (1 << self.bit_pos());
-        val != 0
+    pub fn is_enabled<S: CpuBus>(&self, gb: &S) -> bool {
+        gb.interrupt_enable() & self.mask() != 0
     }
 
-    fn is_enabled(&self, cpu: &mut Cpu) -> bool {
-        let val = read_address(0xFFFF, cpu) & (1 << self.bit_pos());
-        val != 0
+    // The interrupt's bit in IF and IE
+    pub fn mask(&self) -> u8 {
+        1 << self.bit_pos()
     }
 
     fn bit_pos(&self) -> u8 {
@@ -76,7 +132,7 @@ impl Interrupt {
         }
     }
 
-    fn interrupt_address(&self) -> usize {
+    pub fn interrupt_address(&self) -> usize {
         match *self {
             VBlank => 0x40,
             LCD => 0x48,
@@ -86,7 +142,3 @@ impl Interrupt {
         }
     }
 }
-
-fn get_requests(cpu: &mut Cpu) -> u8 {
-    read_address(0xFF0F, cpu)
-}
\ No newline at end of file
@@ -1,6 +1,52 @@
 extern crate piston_window;
 use self::piston_window::*;
 
+// P1: the cpu selects a line with bits 4 and 5 and reads the buttons held on
+// it in the lower nibble, 0 meaning pressed
+pub struct Joypad {
+    pub select: u8,
+    // Buttons in the lower nibble and directions in the upper one
+    pub keys: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad {
+            select: 0,
+            keys: 0xFF,
+        }
+    }
+}
+
+impl Joypad {
+    pub fn read(&self) -> u8 {
+        if ((self.select >> 4) & 1) != 0 {
+            (self.select & 0xF0) | (0xF & self.keys)
+        } else if ((self.select >> 5) & 1) != 0 {
+            (self.select & 0xF0) | ((0xF0 & self.keys) >> 4)
+        } else {
+            0x0F
+        }
+    }
+
+    pub fn write(&mut self, val: u8) {
+        self.select = val & 0xF0;
+    }
+
+    pub fn press(&mut self, bit_mask: u8) {
+        self.keys &= !bit_mask;
+    }
+
+    pub fn release(&mut self, bit_mask: u8) {
+        self.keys |= bit_mask;
+    }
+
+    // Whether a button on one of the selected lines is held down
+    pub fn line_low(&self) -> bool {
+        self.read() & 0x0F != 0x0F
+    }
+}
+
 pub fn joypad_bit(key: Key) -> (bool, u8) {
     let pos = match key {
         Key::A => 3, // start
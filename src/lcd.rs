@@ -1,5 +1,15 @@
-use cpu::*;
+use gameboy::LINE_SCAN_CYCLES;
 use interrupt::Interrupt;
+use ppu::Ppu;
+use scheduler::{Event, Scheduler};
+
+// Clocks spent in mode 2 of a visible line, and the least mode 3 can take.
+// Mode 3 runs longer for SCX, the window and sprites, mode 0 takes the rest.
+pub const OAM_SCAN_CYCLES: u64 = 80;
+pub const TRANSFER_CYCLES: u64 = 172;
+// LY only reads 153 for the first M-cycle of the last line, then 0
+pub const LY_153_CYCLES: u64 = 4;
+const PPU_EVENTS: [Event; 4] = [Event::OamScanEnd, Event::TransferEnd, Event::LineEnd, Event::LyReset];
 
 pub enum LCDC {
     Power,
@@ -15,8 +25,8 @@ pub enum LCDC {
 use self::LCDC::*;
 
 impl LCDC {
-    pub fn is_set(&self, cpu: &mut Cpu) -> bool {
-        read_address(0xFF40, cpu) & self.bit_mask() != 0
+    pub fn is_set(&self, ppu: &Ppu) -> bool {
+        ppu.lcdc & self.bit_mask() != 0
     }
 
     fn bit_mask(&self) -> u8 {
@@ -34,56 +44,132 @@ impl LCDC {
     }
 }
 
-pub fn update_status(frames: usize, cpu: &mut Cpu) -> (bool, ScreenMode) {
-    let ly = read_address(0xFF44, cpu);
-    if ly >= 144 {
-        ScreenMode::VBlank.set(cpu);
-        (false, ScreenMode::VBlank)
-    } else {
-        let (interrupt_enabled, new_mode) = match frames % 456 {
-            0...202 => (stat_is_set(STAT::Mode0HBlankCheck, cpu), ScreenMode::HBlank),
-            203...283 => (stat_is_set(STAT::Mode2OAMCheck, cpu), ScreenMode::Searching),
-            284...455 => (false, ScreenMode::Transferring),
-            _ => unreachable!(),
-        };
+// Starts the ppu at the top of the screen, as when the lcd gets switched on.
+// Returns the interrupts requested as IF bits.
+pub fn start_lcd(ppu: &mut Ppu, scheduler: &mut Scheduler) -> u8 {
+    for &event in PPU_EVENTS.iter() {
+        scheduler.cancel(event);
+    }
+    ppu.ly = 0;
+    ppu.line_start = scheduler.now();
+    ppu.synced_at = scheduler.now();
+    ppu.start_line();
+    ScreenMode::Searching.set(ppu);
+    scheduler.schedule_in(OAM_SCAN_CYCLES, Event::OamScanEnd);
+    compare_lyc(ppu);
+    update_stat_line(ppu)
+}
 
-        if !new_mode.is_set(cpu) {
-            if interrupt_enabled {
-                Interrupt::LCD.request(cpu);
+fn stop_lcd(ppu: &mut Ppu, scheduler: &mut Scheduler) {
+    for &event in PPU_EVENTS.iter() {
+        scheduler.cancel(event);
+    }
+    ppu.ly = 0;
+    ppu.fifo.active = false;
+    ppu.stat_line = false;
+}
+
+// Moves the ppu on to its next mode once the current one is over, returns the
+// interrupts requested on the way as IF bits
+pub fn handle_event(event: Event, timestamp: u64, ppu: &mut Ppu, scheduler: &mut Scheduler) -> u8 {
+    let mut requests = 0;
+    match event {
+        Event::OamScanEnd => {
+            ppu.start_transfer(timestamp);
+            ScreenMode::Transferring.set(ppu);
+            requests |= update_stat_line(ppu);
+            scheduler.schedule(timestamp + TRANSFER_CYCLES, Event::TransferEnd);
+        }
+        Event::TransferEnd => {
+            ppu.sync(timestamp);
+            if ppu.fifo.active {
+                // Every dot shifts out at most one pixel, so mode 3 can't be
+                // over before the rest of them had a dot each
+                let pixels_left = 160 - ppu.fifo.lx as u64;
+                scheduler.schedule(timestamp + pixels_left, Event::TransferEnd);
+                return requests;
             }
-            new_mode.set(cpu);
-            (true, new_mode)
-        } else {
-            (false, new_mode)
+            ppu.finished_line = Some(ppu.ly);
+            ScreenMode::HBlank.set(ppu);
+            requests |= update_stat_line(ppu);
+            scheduler.schedule(ppu.line_start + LINE_SCAN_CYCLES as u64, Event::LineEnd);
         }
-
+        Event::LineEnd => {
+            ppu.line_start = timestamp;
+            requests |= increment_ly(ppu);
+            if ppu.ly < 144 {
+                ScreenMode::Searching.set(ppu);
+                ppu.start_line();
+                scheduler.schedule(timestamp + OAM_SCAN_CYCLES, Event::OamScanEnd);
+            } else {
+                if ppu.ly == 144 {
+                    ScreenMode::VBlank.set(ppu);
+                    ppu.frame_ready = true;
+                } else if ppu.ly == 153 {
+                    scheduler.schedule(timestamp + LY_153_CYCLES, Event::LyReset);
+                }
+                scheduler.schedule(timestamp + LINE_SCAN_CYCLES as u64, Event::LineEnd);
+            }
+            // Mode and LY change together, so going from one enabled source
+            // straight to another doesn't make a new edge
+            requests |= update_stat_line(ppu);
+        }
+        Event::LyReset => {
+            ppu.ly = 0;
+            compare_lyc(ppu);
+            requests |= update_stat_line(ppu);
+        }
+        _ => {}
     }
-
+    requests
 }
 
-pub fn increment_ly(cpu: &mut Cpu) {
-    let val = (read_address(0xFF44, cpu) + 1) % 154;
-    write_address(0xFF44, (read_address(0xFF44, cpu) + 1) % 154, cpu);
+// Moves LY to the next line and returns the VBlank request, STAT goes through
+// update_stat_line. LY already went back to 0 during line 153, which is the
+// only time it reads 0 at the end of a line in VBlank.
+pub fn increment_ly(ppu: &mut Ppu) -> u8 {
+    let mut requests = 0;
+    let val = if ppu.ly == 0 && ScreenMode::VBlank.is_set(ppu) {
+        0
+    } else {
+        (ppu.ly + 1) % 154
+    };
+    ppu.ly = val;
     if val == 144 {
-        Interrupt::VBlank.request(cpu);
-    }
-    if stat_is_set(STAT::Mode1VBlankCheck, cpu) && val == 144 {
-        Interrupt::LCD.request(cpu);
-    }
-    if stat_is_set(STAT::LYLYCCheck, cpu) && val == read_address(0xFF45, cpu) {
-        Interrupt::LCD.request(cpu);
+        requests |= Interrupt::VBlank.mask();
     }
+    compare_lyc(ppu);
+    requests
+}
 
-    // set lcy=ly comparison flag
-    if val == read_address(0xFF45, cpu) {
-        let stat = read_address(0xFF41, cpu);
-        write_address(0xFF41, stat | 0b100, cpu);
+// Sets the LY=LYC coincidence flag
+pub fn compare_lyc(ppu: &mut Ppu) {
+    if ppu.ly == ppu.lyc {
+        ppu.stat |= 0b100;
     } else {
-        let stat = read_address(0xFF41, cpu);
-        write_address(0xFF41, stat & (0xFF - 0b100), cpu);
+        ppu.stat &= 0xFF - 0b100;
     }
 }
 
+// Whether any enabled STAT source is active. They are all ORed into the one
+// line the LCD interrupt is requested from.
+fn stat_line_high(ppu: &Ppu) -> bool {
+    (stat_is_set(STAT::LYLYCCheck, ppu) && stat_is_set(STAT::LYLYCSignal, ppu))
+        || (stat_is_set(STAT::Mode0HBlankCheck, ppu) && ScreenMode::HBlank.is_set(ppu))
+        || (stat_is_set(STAT::Mode1VBlankCheck, ppu) && ScreenMode::VBlank.is_set(ppu))
+        || (stat_is_set(STAT::Mode2OAMCheck, ppu) && ScreenMode::Searching.is_set(ppu))
+}
+
+// Recomputes the STAT line after anything feeding it changed. The interrupt
+// is only requested on its rising edge, so while one source holds the line
+// high the others can't fire.
+pub fn update_stat_line(ppu: &mut Ppu) -> u8 {
+    let high = LCDC::Power.is_set(ppu) && stat_line_high(ppu);
+    let rising = high && !ppu.stat_line;
+    ppu.stat_line = high;
+    cond!(rising, Interrupt::LCD.mask(), 0)
+}
+
 pub enum STAT {
     LYLYCCheck,
     Mode2OAMCheck,
@@ -104,14 +190,14 @@ pub enum ScreenMode {
 use self::ScreenMode::*;
 
 impl ScreenMode {
-    pub fn is_set(&self, cpu: &mut Cpu) -> bool {
-        let val = read_stat_address(cpu) & self.stat_mask();
+    pub fn is_set(&self, ppu: &Ppu) -> bool {
+        let val = read_stat_address(ppu) & self.stat_mask();
         val == self.val()
     }
 
-    pub fn set(&self, cpu: &mut Cpu) {
-        let val = read_stat_address(cpu) & (0xFF - self.stat_mask());
-        write_address(0xFF41, val | self.val(), cpu);
+    pub fn set(&self, ppu: &mut Ppu) {
+        let val = read_stat_address(ppu) & (0xFF - self.stat_mask());
+        ppu.stat = val | self.val();
     }
 
     pub fn val(&self) -> u8 {
@@ -128,42 +214,62 @@ impl ScreenMode {
     }
 }
 
-pub fn read_stat_address(cpu: &mut Cpu) -> u8 {
-    let val = (1 << 7) | read_address(0xFF41, cpu);
-    if LCDC::Power.is_set(cpu) {
+pub fn read_stat_address(ppu: &Ppu) -> u8 {
+    let val = (1 << 7) | ppu.stat;
+    if LCDC::Power.is_set(ppu) {
         val
     } else {
         (val & (0xFF - 0b11)) | 1
     }
 }
 
-pub fn write_lcdc_address(val: u8, cpu: &mut Cpu) {
-    // Turning off lcd
+pub fn write_lcdc_address(val: u8, ppu: &mut Ppu, scheduler: &mut Scheduler) -> u8 {
+    let was_on = LCDC::Power.is_set(ppu);
+    ppu.lcdc = val;
+    // Turning off lcd stops the ppu and resets LY
     if (val & 0x80) == 0 {
-        // reset LY
-        write_address(0xFF44, 0, cpu);
+        stop_lcd(ppu, scheduler);
+        0
+    } else if !was_on {
+        start_lcd(ppu, scheduler)
+    } else {
+        0
     }
-    write_address(0xFF40, val, cpu);
 }
 
-pub fn write_stat_address(val: u8, cpu: &mut Cpu) {
-    let prev_val = read_address(0xFF41, cpu);
-    let write_val = (val & (0xFF - 0b111)) | (prev_val & 0b111);
-    write_address(0xFF41, write_val, cpu)
+// On DMG the HBlank, VBlank and LY=LYC sources are all enabled for a cycle
+// before the written value lands, so any write while one of them is active
+// requests the interrupt
+pub fn write_stat_address(val: u8, ppu: &mut Ppu) -> u8 {
+    let prev_val = ppu.stat;
+    ppu.stat = prev_val | 0b0101_1000;
+    let mut requests = update_stat_line(ppu);
+    ppu.stat = (val & (0xFF - 0b111)) | (prev_val & 0b111);
+    requests |= update_stat_line(ppu);
+    requests
+}
+
+pub fn write_lyc_address(val: u8, ppu: &mut Ppu) -> u8 {
+    ppu.lyc = val;
+    if !LCDC::Power.is_set(ppu) {
+        return 0;
+    }
+    compare_lyc(ppu);
+    update_stat_line(ppu)
 }
 
-pub fn stat_is_set(stat: STAT, cpu: &mut Cpu) -> bool {
-    read_stat_address(cpu) & stat_bit(stat) != 0
+pub fn stat_is_set(stat: STAT, ppu: &Ppu) -> bool {
+    read_stat_address(ppu) & stat_bit(stat) != 0
 }
 
-pub fn screen_mode_is_set(screen_mode: ScreenMode, cpu: &mut Cpu) -> bool {
-    let val = read_stat_address(cpu) & stat_bit(STAT::SM(screen_mode));
+pub fn screen_mode_is_set(screen_mode: ScreenMode, ppu: &Ppu) -> bool {
+    let val = read_stat_address(ppu) & stat_bit(STAT::SM(screen_mode));
     val == screen_mode_val(screen_mode)
 }
 
-pub fn screen_mode_set(screen_mode: ScreenMode, cpu: &mut Cpu) {
-    let val = read_stat_address(cpu) & (0xFF - stat_bit(STAT::SM(screen_mode)));
-    write_address(0xFF41, val | screen_mode_val(screen_mode), cpu);
+pub fn screen_mode_set(screen_mode: ScreenMode, ppu: &mut Ppu) {
+    let val = read_stat_address(ppu) & (0xFF - stat_bit(STAT::SM(screen_mode)));
+    ppu.stat = val | screen_mode_val(screen_mode);
 }
 
 pub fn screen_mode_val(screen_mode: ScreenMode) -> u8 {
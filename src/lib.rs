@@ -1,13 +1,23 @@
 #[macro_use]
 pub mod utility;
+pub mod bus;
+pub mod cartridge;
+pub mod header;
+pub mod mbc;
+pub mod rtc;
 pub mod opcode;
 pub mod register;
 pub mod instr;
-pub mod cpu;
+pub mod sm83;
 pub mod flag;
 pub mod lcd;
 pub mod ppu;
 pub mod interrupt;
+pub mod timer;
+pub mod serial;
+pub mod dma;
+pub mod scheduler;
 pub mod keyboard;
 pub mod joypad;
-pub mod apu;
\ No newline at end of file
+pub mod apu;
+pub mod gameboy;
\ No newline at end of file
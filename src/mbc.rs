@@ -0,0 +1,367 @@
+use rtc::Rtc;
+
+// Memory bank controllers map the cartridge's rom and ram banks into the
+// address space. They are configured by writing to the rom area.
+pub trait Mbc {
+    fn read_rom(&self, rom: &[u8], address: usize) -> u8;
+    fn read_ram(&self, ram: &[u8], address: usize) -> u8;
+    // Returns whether the write reached the ram or clock
+    fn write_ram(&mut self, ram: &mut [u8], address: usize, val: u8) -> bool;
+    fn write_control(&mut self, address: usize, val: u8);
+    fn ram_enabled(&self) -> bool;
+    // The rom bank mapped at a rom address
+    fn rom_bank_at(&self, address: usize) -> usize;
+
+    // Whether the rumble motor is currently switched on
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    // Advances anything clocked inside the cartridge, like the MBC3 timer
+    fn tick(&mut self, _cycles: usize) {}
+
+    // Extra state kept after the ram in save files
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_state(&mut self, _data: &[u8]) {}
+}
+
+// Index of `address` inside `bank`, wrapped around to the size of the memory
+// so that selecting a bank the cartridge doesn't have mirrors like hardware
+pub fn bank_index(mem: &[u8], bank: usize, bank_size: usize, address: usize) -> Option<usize> {
+    if mem.is_empty() {
+        None
+    } else {
+        Some((bank * bank_size + address % bank_size) % mem.len())
+    }
+}
+
+pub fn read_rom_bank(rom: &[u8], bank: usize, address: usize) -> u8 {
+    bank_index(rom, bank, 0x4000, address).map_or(0xFF, |idx| rom[idx])
+}
+
+pub fn read_ram_bank(ram: &[u8], bank: usize, address: usize) -> u8 {
+    bank_index(ram, bank, 0x2000, address).map_or(0xFF, |idx| ram[idx])
+}
+
+pub fn write_ram_bank(ram: &mut [u8], bank: usize, address: usize, val: u8) -> bool {
+    match bank_index(ram, bank, 0x2000, address) {
+        Some(idx) => {
+            ram[idx] = val;
+            true
+        }
+        None => false,
+    }
+}
+
+// Enabling ram takes a value with 0xA in the lower nibble
+fn ram_enable_val(val: u8) -> bool {
+    val & 0x0F == 0x0A
+}
+
+pub struct RomOnly;
+
+impl Mbc for RomOnly {
+    fn read_rom(&self, rom: &[u8], address: usize) -> u8 {
+        read_rom_bank(rom, address / 0x4000, address)
+    }
+
+    fn read_ram(&self, ram: &[u8], address: usize) -> u8 {
+        read_ram_bank(ram, 0, address)
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: usize, val: u8) -> bool {
+        write_ram_bank(ram, 0, address, val)
+    }
+
+    fn write_control(&mut self, _address: usize, _val: u8) {}
+
+    fn ram_enabled(&self) -> bool {
+        true
+    }
+
+    fn rom_bank_at(&self, address: usize) -> usize {
+        address / 0x4000
+    }
+}
+
+#[derive(Default)]
+pub struct Mbc1 {
+    ram_enabled: bool,
+    lo_rom_bank: usize,
+    hi_bank: usize,
+    ram_banking_mode: bool,
+}
+
+impl Mbc1 {
+    fn rom_bank(&self) -> usize {
+        // Bank 0x00, 0x20, 0x40 and 0x60 can't be selected, the next bank is used instead
+        let lo_bank = cond!(self.lo_rom_bank == 0, 1, self.lo_rom_bank);
+        (self.hi_bank << 5) | lo_bank
+    }
+
+    fn ram_bank(&self) -> usize {
+        cond!(self.ram_banking_mode, self.hi_bank, 0)
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, rom: &[u8], address: usize) -> u8 {
+        read_rom_bank(rom, self.rom_bank_at(address), address)
+    }
+
+    fn rom_bank_at(&self, address: usize) -> usize {
+        match address {
+            0x0000...0x3FFF => cond!(self.ram_banking_mode, self.hi_bank << 5, 0),
+            _ => self.rom_bank(),
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: usize) -> u8 {
+        cond!(self.ram_enabled, read_ram_bank(ram, self.ram_bank(), address), 0xFF)
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: usize, val: u8) -> bool {
+        let bank = self.ram_bank();
+        self.ram_enabled && write_ram_bank(ram, bank, address, val)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn write_control(&mut self, address: usize, val: u8) {
+        match address {
+            0x0000...0x1FFF => self.ram_enabled = ram_enable_val(val),
+            0x2000...0x3FFF => self.lo_rom_bank = (val & 0x1F) as usize,
+            0x4000...0x5FFF => self.hi_bank = (val & 0b11) as usize,
+            _ => self.ram_banking_mode = (val & 1) == 1,
+        }
+    }
+}
+
+// MBC2 has 512 half-bytes of ram built in, mirrored across 0xA000-0xBFFF
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: usize,
+}
+
+impl Default for Mbc2 {
+    fn default() -> Mbc2 {
+        Mbc2 {
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, rom: &[u8], address: usize) -> u8 {
+        read_rom_bank(rom, self.rom_bank_at(address), address)
+    }
+
+    fn rom_bank_at(&self, address: usize) -> usize {
+        cond!(address < 0x4000, 0, self.rom_bank)
+    }
+
+    fn read_ram(&self, ram: &[u8], address: usize) -> u8 {
+        if self.ram_enabled && !ram.is_empty() {
+            // Only the lower 4 bits are wired up, the rest read back as 1
+            0xF0 | ram[address % MBC2_RAM_SIZE]
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: usize, val: u8) -> bool {
+        if self.ram_enabled && !ram.is_empty() {
+            ram[address % MBC2_RAM_SIZE] = val & 0x0F;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn write_control(&mut self, address: usize, val: u8) {
+        match address {
+            // Bit 8 of the address picks between ram enable and rom bank
+            0x0000...0x3FFF => {
+                if address & 0x100 == 0 {
+                    self.ram_enabled = ram_enable_val(val);
+                } else {
+                    self.rom_bank = match val & 0x0F {
+                        0 => 1,
+                        bank => bank as usize,
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Mbc3 {
+    ram_enabled: bool,
+    rom_bank: usize,
+    // 0x00-0x03 select a ram bank, 0x08-0x0C a clock register
+    ram_bank: usize,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            rom_bank: 1,
+            rtc: cond!(has_rtc, Some(Rtc::default()), None),
+            ..Default::default()
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, rom: &[u8], address: usize) -> u8 {
+        read_rom_bank(rom, self.rom_bank_at(address), address)
+    }
+
+    fn rom_bank_at(&self, address: usize) -> usize {
+        cond!(address < 0x4000, 0, self.rom_bank)
+    }
+
+    fn read_ram(&self, ram: &[u8], address: usize) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_bank, &self.rtc) {
+            (0x00...0x03, _) => read_ram_bank(ram, self.ram_bank, address),
+            (0x08...0x0C, &Some(ref rtc)) => rtc.read(self.ram_bank),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: usize, val: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match (self.ram_bank, &mut self.rtc) {
+            (0x00...0x03, _) => write_ram_bank(ram, self.ram_bank, address, val),
+            (0x08...0x0C, &mut Some(ref mut rtc)) => {
+                rtc.write(self.ram_bank, val);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn write_control(&mut self, address: usize, val: u8) {
+        match address {
+            0x0000...0x1FFF => self.ram_enabled = ram_enable_val(val),
+            0x2000...0x3FFF => {
+                self.rom_bank = match val & 0x7F {
+                    0 => 1,
+                    bank => bank as usize, // Select 7 bits
+                }
+            }
+            0x4000...0x5FFF => self.ram_bank = (val & 0x0F) as usize,
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.rtc.as_ref().map_or(Vec::new(), |rtc| rtc.save())
+    }
+
+    fn load_state(&mut self, data: &[u8]) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.load(data);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    has_rumble: bool,
+    rumble_on: bool,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom_bank: 1,
+            has_rumble: has_rumble,
+            ..Default::default()
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, rom: &[u8], address: usize) -> u8 {
+        read_rom_bank(rom, self.rom_bank_at(address), address)
+    }
+
+    fn rom_bank_at(&self, address: usize) -> usize {
+        cond!(address < 0x4000, 0, self.rom_bank)
+    }
+
+    fn read_ram(&self, ram: &[u8], address: usize) -> u8 {
+        cond!(self.ram_enabled, read_ram_bank(ram, self.ram_bank, address), 0xFF)
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: usize, val: u8) -> bool {
+        let bank = self.ram_bank;
+        self.ram_enabled && write_ram_bank(ram, bank, address, val)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn write_control(&mut self, address: usize, val: u8) {
+        match address {
+            0x0000...0x1FFF => self.ram_enabled = ram_enable_val(val),
+            // Lower 8 bits of the rom bank, unlike MBC1 bank 0 can be selected
+            0x2000...0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as usize,
+            // 9th bit of the rom bank
+            0x3000...0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((val as usize & 1) << 8),
+            0x4000...0x5FFF => {
+                // Rumble carts wire bit 3 to the motor instead of the ram bank
+                if self.has_rumble {
+                    self.rumble_on = val & 0x08 != 0;
+                    self.ram_bank = (val & 0x07) as usize;
+                } else {
+                    self.ram_bank = (val & 0x0F) as usize;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble_on
+    }
+}
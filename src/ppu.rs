@@ -1,268 +1,535 @@
 extern crate image;
 use self::image::{ImageBuffer, Rgba};
-use cpu::*;
 use lcd::*;
+use scheduler::Scheduler;
+use std::collections::VecDeque;
 
-pub fn render_scanline(
-    ly: u8,
-    screen_buffer: &mut [u8; 256 * 256],
-    canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
-    cpu: &mut Cpu,
-) {
-    if ly > 143 {
-        return;
+// The picture processing unit: video ram, OAM and the lcd registers
+pub struct Ppu {
+    pub vram: Vec<u8>,
+    pub oam: Vec<u8>,
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    // Debug toggles per layer: 0 follows LCDC, 1 always draws, 2 never does
+    pub background_mode: u8,
+    pub window_mode: u8,
+    pub sprite_mode: u8,
+    pub frame_ready: bool,
+    // Scanline finished since one was last drawn
+    pub finished_line: Option<u8>,
+    // Clock the pixel fifo has been run up to
+    pub synced_at: u64,
+    // Clock the current line started at
+    pub line_start: u64,
+    // Up to 10 sprites the OAM scan found on the current line, in OAM order
+    pub line_sprites: Vec<Sprite>,
+    pub fifo: PixelFifo,
+    // Shades of the line being drawn
+    pub line_buffer: [u8; 160],
+    // Row of the window to draw next. It only moves on after lines the window
+    // was drawn on, not with LY.
+    pub window_line: u8,
+    // Set once LY matched WY at the start of a line this frame
+    pub window_y_reached: bool,
+    // All enabled STAT sources ORed together, the LCD interrupt fires when it
+    // goes high
+    pub stat_line: bool,
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu {
+            vram: vec![0; 0x2000],
+            oam: vec![0; 0xA0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            background_mode: 0,
+            window_mode: 0,
+            sprite_mode: 0,
+            frame_ready: false,
+            finished_line: None,
+            synced_at: 0,
+            line_start: 0,
+            line_sprites: Vec::with_capacity(MAX_LINE_SPRITES),
+            fifo: PixelFifo::default(),
+            line_buffer: [0; 160],
+            window_line: 0,
+            window_y_reached: false,
+            stat_line: false,
+        }
     }
-    if LCDC::Power.is_set(cpu) {
-        write_background_line(ly, screen_buffer, cpu);
-        write_window_line(ly, screen_buffer, cpu);
-        write_sprites_line(ly, screen_buffer, cpu);
-        buffer_line_to_image_buffer(ly, canvas, screen_buffer)
-    } else {
-        clear_buffer(screen_buffer);
-        buffer_line_to_image_buffer(ly, canvas, screen_buffer)
+}
+
+impl Ppu {
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0x8000...0x9FFF => self.vram[address - 0x8000],
+            0xFE00...0xFE9F => self.oam[address - 0xFE00],
+            0xFF40 => self.lcdc,
+            0xFF41 => read_stat_address(self),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    // Switching the lcd on or off starts or stops the mode events. Returns the
+    // interrupts the write requested as IF bits.
+    pub fn write(&mut self, address: usize, val: u8, scheduler: &mut Scheduler) -> u8 {
+        match address {
+            0x8000...0x9FFF => self.vram[address - 0x8000] = val,
+            0xFE00...0xFE9F => self.oam[address - 0xFE00] = val,
+            0xFF40 => return write_lcdc_address(val, self, scheduler),
+            0xFF41 => return write_stat_address(val, self),
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+            0xFF44 => self.ly = 0,
+            0xFF45 => return write_lyc_address(val, self),
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+            _ => {}
+        }
+        0
+    }
+
+    // Runs the pixel fifo dot by dot up to `now`, so registers written in the
+    // middle of mode 3 only affect the pixels after the write
+    pub fn sync(&mut self, now: u64) {
+        while self.synced_at < now && self.fifo.active {
+            step_dot(self);
+            self.synced_at += 1;
+        }
+        if self.synced_at < now {
+            self.synced_at = now;
+        }
+    }
+
+    // Mode 2 is starting. WY is only compared against LY here, so moving it
+    // mid line or past LY doesn't bring the window in.
+    pub fn start_line(&mut self) {
+        if self.ly == 0 {
+            self.window_line = 0;
+            self.window_y_reached = false;
+        }
+        if self.ly == self.wy {
+            self.window_y_reached = true;
+        }
+    }
+
+    // Mode 2 is over: picks the sprites on the line and starts mode 3
+    pub fn start_transfer(&mut self, timestamp: u64) {
+        self.sync(timestamp);
+        oam_scan(self);
+        let discard = self.scx % 8;
+        self.fifo = PixelFifo {
+            active: true,
+            discard: discard,
+            ..PixelFifo::default()
+        };
     }
 }
 
-fn clear_buffer(screen_buffer: &mut [u8; 256 * 256]) {
-    for i in 0..(256 * 256) {
-        screen_buffer[i] = 0;
+// A sprite on the current line. Its tile and attributes are only read from
+// OAM when it gets fetched.
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub index: usize,
+    pub x: u8,
+    pub y: u8,
+    pub fetched: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    palette: usize,
+    // OAM attribute bit 7: only drawn over background color 0
+    behind_background: bool,
+}
+
+const TRANSPARENT: ObjPixel = ObjPixel {
+    color: 0,
+    palette: 0xFF48,
+    behind_background: false,
+};
+
+// Each step of the fetcher takes 2 dots, Push is retried every dot until the
+// background fifo is empty
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FetchStep {
+    Tile,
+    Low,
+    High,
+    Push,
+}
+
+impl Default for FetchStep {
+    fn default() -> FetchStep {
+        FetchStep::Tile
     }
 }
 
-pub fn write_background_line(ly: u8, buffer: &mut [u8; 256 * 256], cpu: &mut Cpu) {
-    if (cpu.background_mode == 0 && LCDC::BGEnable.is_set(cpu)) || cpu.background_mode == 1 {
-        let start = cond!(LCDC::BGTileMap.is_set(cpu), 0x9C00, 0x9800);
-        let tile_map_start = cond!(LCDC::Tileset.is_set(cpu), 0x8000, 0x8800);
-        let scroll_x = read_address(0xFF43, cpu) as usize;
-        let scroll_y = read_address(0xFF42, cpu) as usize;
-        let start_offset = 32 * (((ly as usize + scroll_y) / 8) % 32);
-        let tile_y = (256 + 8 * (start_offset / 32) - scroll_y) % 256;
-
-        for offset in start_offset..(start_offset + 32) {
-            let tile_x = (256 + 8 * (offset % 32) - scroll_x) % 256;
-            // Skip painting the tiles that are not visible
-            if tile_x <= 160 || tile_x >= 248 {
-                let tile_num = cond!(
-                    LCDC::Tileset.is_set(cpu),
-                    read_address(start + offset, cpu) as usize,
-                    (128 as i16 + read_address_i8(start + offset, cpu) as i16) as usize
-                );
-
-                write_bg_tile_line(
-                    ly,
-                    tile_num,
-                    tile_x as usize,
-                    tile_y as usize,
-                    tile_map_start,
-                    0xFF47,
-                    buffer,
-                    cpu,
-                );
-            }
+// Fetches a row of 8 background or window pixels at a time
+#[derive(Default)]
+struct Fetcher {
+    step: FetchStep,
+    dots: u8,
+    // Tiles pushed since the line or the window started
+    tile_x: u8,
+    tile_num: u8,
+    low: u8,
+    high: u8,
+    // The first fetch of a line is thrown away
+    warmed_up: bool,
+}
+
+// State of mode 3: the background and sprite fifos and the fetchers that
+// fill them. A pixel is shifted out every dot unless the background fifo is
+// empty or a sprite is being fetched.
+#[derive(Default)]
+pub struct PixelFifo {
+    pub active: bool,
+    // Screen x of the next pixel shifted out
+    pub lx: u8,
+    // Pixels of the first tile still to throw away for SCX
+    discard: u8,
+    // Raw color indices of the background and window, before BGP
+    background: VecDeque<u8>,
+    sprites: VecDeque<ObjPixel>,
+    fetcher: Fetcher,
+    in_window: bool,
+    // Sprite waiting for the background fetcher, and the dots spent on it
+    sprite_fetch: Option<usize>,
+    sprite_dots: u8,
+}
+
+const SPRITE_FETCH_DOTS: u8 = 6;
+const MAX_LINE_SPRITES: usize = 10;
+
+fn layer_enabled(mode: u8, flag: LCDC, ppu: &Ppu) -> bool {
+    (mode == 0 && flag.is_set(ppu)) || mode == 1
+}
+
+fn sprite_height(ppu: &Ppu) -> u8 {
+    cond!(LCDC::SpriteSize.is_set(ppu), 16, 8)
+}
+
+fn oam_scan(ppu: &mut Ppu) {
+    let height = sprite_height(ppu) as u16;
+    let ly = ppu.ly as u16;
+    let mut sprites = Vec::with_capacity(MAX_LINE_SPRITES);
+    for i in 0..40 {
+        // Only the first 10 sprites on a line get drawn, offscreen x or not
+        if sprites.len() == MAX_LINE_SPRITES {
+            break;
+        }
+        let y = ppu.oam[i * 4];
+        let top = y as u16;
+        // OAM y is the screen y + 16
+        if ly + 16 >= top && ly + 16 < top + height {
+            sprites.push(Sprite {
+                index: i,
+                x: ppu.oam[i * 4 + 1],
+                y: y,
+                fetched: false,
+            });
         }
-    } else {
     }
+    ppu.line_sprites = sprites;
 }
 
-pub fn write_window_line(ly: u8, buffer: &mut [u8; 256 * 256], cpu: &mut Cpu) {
-    if (cpu.window_mode == 0 && LCDC::WindowEnable.is_set(cpu)) || cpu.window_mode == 1 {
-        let start = cond!(LCDC::WindowTileMap.is_set(cpu), 0x9C00, 0x9800);
-        let tile_map_start = cond!(LCDC::Tileset.is_set(cpu), 0x8000, 0x8800);
-
-        let scroll_x = read_address(0xFF4B, cpu) as usize;
-        let scroll_y = read_address(0xFF4A, cpu) as usize;
-        if ly as usize >= scroll_y {
-            let start_offset = (32 * ((ly as usize - scroll_y) / 8)) as usize;
-            for offset in start_offset..(32 + start_offset) {
-                let tile_num = cond!(
-                    LCDC::Tileset.is_set(cpu),
-                    read_address(start + offset, cpu) as usize,
-                    (128 + read_address_i8(start + offset, cpu) as i16) as usize
-                );
-                write_window_tile_line(
-                    ly,
-                    tile_num,
-                    8 * (offset as isize % 32) + scroll_x as isize - 7,
-                    8 * (offset as isize / 32) + scroll_y as isize,
-                    tile_map_start,
-                    buffer,
-                    cpu,
-                );
+// One dot of mode 3
+fn step_dot(ppu: &mut Ppu) {
+    if !ppu.fifo.in_window && window_starts(ppu) {
+        ppu.fifo.in_window = true;
+        // With WX below 7 the window starts at the left edge, already
+        // scrolled by the columns left of it
+        if ppu.wx < 7 {
+            ppu.fifo.discard = 7 - ppu.wx;
+        }
+        ppu.fifo.background.clear();
+        ppu.fifo.fetcher = Fetcher {
+            warmed_up: ppu.fifo.fetcher.warmed_up,
+            ..Fetcher::default()
+        };
+    }
+
+    if ppu.fifo.sprite_fetch.is_none() && ppu.fifo.discard == 0 {
+        ppu.fifo.sprite_fetch = next_sprite(ppu);
+        ppu.fifo.sprite_dots = 0;
+    }
+
+    if let Some(sprite) = ppu.fifo.sprite_fetch {
+        // The background fetcher finishes its tile before the sprite is
+        // fetched, the sprite fetch starts on the dot it does
+        if !fetcher_ready(ppu) {
+            fetch_dot(ppu);
+            if !fetcher_ready(ppu) {
+                return;
             }
         }
-    } else {
+        ppu.fifo.sprite_dots += 1;
+        if ppu.fifo.sprite_dots == SPRITE_FETCH_DOTS {
+            fetch_sprite(sprite, ppu);
+            ppu.fifo.sprite_fetch = None;
+        }
+        return;
     }
+
+    shift_pixel(ppu);
+    fetch_dot(ppu);
 }
 
-pub fn write_sprites_line(ly: u8, buffer: &mut [u8; 256 * 256], cpu: &mut Cpu) {
-    if (cpu.sprite_mode == 0 && LCDC::SpritesEnable.is_set(cpu)) || cpu.sprite_mode == 1 {
-        let start = 0xFE00;
-        let square_sprites = !LCDC::SpriteSize.is_set(cpu);
-        for i in 0..40 {
-            let address = start + i * 4;
-            let y = read_address(address, cpu) as isize;
-            if y == 0 {
-                continue;
-            } // sprite is off screen
-
-            let (x, tile_num, sprite_flag) = (
-                read_address(address + 1, cpu) as isize,
-                read_address(address + 2, cpu) as usize,
-                read_address(address + 3, cpu),
-            );
-            let pallette_address = cond!(sprite_flag & 0x10 == 0, 0xFF48, 0xFF49);
-            let h_flip = (sprite_flag & 0b00100000) != 0;
-            let v_flip = (sprite_flag & 0b01000000) != 0;
-
-            if square_sprites && ((ly as isize) >= y - 8 || (ly as isize) < y - 16) {
-                continue;
-            } else if !square_sprites && ((ly as isize) >= y || ((ly as isize) < y - 16)) {
-                continue;
-            }
+fn fetcher_ready(ppu: &Ppu) -> bool {
+    ppu.fifo.fetcher.step == FetchStep::Push && !ppu.fifo.background.is_empty()
+}
+
+// The window starts when the pixel about to come out is at WX - 7. WX 166
+// only reaches the last column, and past that the window never shows.
+fn window_starts(ppu: &Ppu) -> bool {
+    let lx = ppu.fifo.lx;
+    layer_enabled(ppu.window_mode, LCDC::WindowEnable, ppu)
+        && ppu.window_y_reached
+        && ppu.fifo.discard == 0
+        && (lx as u16 + 7 == ppu.wx as u16 || (lx == 0 && ppu.wx < 7))
+}
+
+// The sprite to fetch next out of the ones on the line that start at or
+// before the current pixel. On DMG the one with the smaller x wins where
+// sprites overlap, then the one first in OAM, and since pixels already in the
+// sprite fifo are never replaced the winner has to be fetched first.
+fn next_sprite(ppu: &mut Ppu) -> Option<usize> {
+    if !layer_enabled(ppu.sprite_mode, LCDC::SpritesEnable, ppu) {
+        return None;
+    }
+    // OAM x is the screen x + 8
+    let lx = ppu.fifo.lx as u16 + 8;
+    let next = ppu.line_sprites
+        .iter_mut()
+        .filter(|sprite| !sprite.fetched && sprite.x as u16 <= lx)
+        .min_by_key(|sprite| (sprite.x, sprite.index));
+    match next {
+        Some(sprite) => {
+            sprite.fetched = true;
+            Some(sprite.index)
+        }
+        None => None,
+    }
+}
+
+fn shift_pixel(ppu: &mut Ppu) {
+    let color = match ppu.fifo.background.pop_front() {
+        Some(color) => color,
+        None => return,
+    };
+    let sprite = ppu.fifo.sprites.pop_front().unwrap_or(TRANSPARENT);
+    if ppu.fifo.discard > 0 {
+        ppu.fifo.discard -= 1;
+        return;
+    }
+    // Palettes are looked up as the pixel comes out, so writes to them take
+    // effect mid line. Sprites behind the background only show through its
+    // color 0, whatever shade BGP maps that to.
+    let sprite_visible = sprite.color != 0
+        && !(sprite.behind_background && color != 0)
+        && layer_enabled(ppu.sprite_mode, LCDC::SpritesEnable, ppu);
+    let shade = if sprite_visible {
+        lookup_color_idx(sprite.palette, sprite.color, ppu)
+    } else {
+        lookup_color_idx(0xFF47, color, ppu)
+    };
+    ppu.line_buffer[ppu.fifo.lx as usize] = shade;
+    ppu.fifo.lx += 1;
+    if ppu.fifo.lx == 160 {
+        ppu.fifo.active = false;
+        if ppu.fifo.in_window {
+            ppu.window_line = ppu.window_line.wrapping_add(1);
+        }
+    }
+}
 
-            if square_sprites {
-                write_sprite_tile_line(
-                    ly as usize,
-                    tile_num,
-                    x - 8,
-                    y - 16,
-                    pallette_address,
-                    h_flip,
-                    v_flip,
-                    buffer,
-                    cpu,
-                );
+fn fetch_dot(ppu: &mut Ppu) {
+    if ppu.fifo.fetcher.step == FetchStep::Push {
+        push_tile(ppu);
+        return;
+    }
+    ppu.fifo.fetcher.dots += 1;
+    if ppu.fifo.fetcher.dots < 2 {
+        return;
+    }
+    ppu.fifo.fetcher.dots = 0;
+    match ppu.fifo.fetcher.step {
+        FetchStep::Tile => {
+            ppu.fifo.fetcher.tile_num = fetch_tile_num(ppu);
+            ppu.fifo.fetcher.step = FetchStep::Low;
+        }
+        FetchStep::Low => {
+            ppu.fifo.fetcher.low = fetch_tile_data(0, ppu);
+            ppu.fifo.fetcher.step = FetchStep::High;
+        }
+        FetchStep::High => {
+            ppu.fifo.fetcher.high = fetch_tile_data(1, ppu);
+            if ppu.fifo.fetcher.warmed_up {
+                ppu.fifo.fetcher.step = FetchStep::Push;
+                push_tile(ppu);
             } else {
-                let (tile_hi, tile_lo) = if v_flip {
-                    (tile_num | 0x01, tile_num & 0xFE)
-                } else {
-                    (tile_num & 0xFE, tile_num | 0x01)
-                };
-
-                if (ly as isize) < (y - 8) {
-                    write_sprite_tile_line(
-                        ly as usize,
-                        tile_hi,
-                        x - 8,
-                        y - 16,
-                        pallette_address,
-                        h_flip,
-                        v_flip,
-                        buffer,
-                        cpu,
-                    );
-                } else {
-                    write_sprite_tile_line(
-                        ly as usize,
-                        tile_lo,
-                        x - 8,
-                        y - 8,
-                        pallette_address,
-                        h_flip,
-                        v_flip,
-                        buffer,
-                        cpu,
-                    );
-                }
+                ppu.fifo.fetcher.warmed_up = true;
+                ppu.fifo.fetcher.step = FetchStep::Tile;
             }
         }
+        FetchStep::Push => unreachable!(),
     }
 }
 
-pub fn write_bg_tile_line(
-    ly: u8,
-    tile_num: usize,
-    x: usize,
-    y: usize,
-    tile_map_start: usize,
-    pallette_address: usize,
-    buffer: &mut [u8; 256 * 256],
-    cpu: &mut Cpu,
-) {
-    let address_start = tile_map_start + 16 * tile_num;
-    let row = (256 + ly as usize - y) % 256;
-    let left_line = read_address(address_start + row * 2, cpu) as u16;
-    let right_line = read_address(address_start + row * 2 + 1, cpu) as u16;
-    let y_idx = (y + row) % 256;
-    let buffer_start = 256 * y_idx as usize;
-
+fn push_tile(ppu: &mut Ppu) {
+    if !ppu.fifo.background.is_empty() {
+        return;
+    }
+    // On DMG clearing LCDC bit 0 blanks the window along with the background
+    let visible = if ppu.fifo.in_window {
+        layer_enabled(ppu.window_mode, LCDC::BGEnable, ppu)
+    } else {
+        layer_enabled(ppu.background_mode, LCDC::BGEnable, ppu)
+    };
+    let (low, high) = (ppu.fifo.fetcher.low, ppu.fifo.fetcher.high);
     for col in 0..8 {
-        let pos_offset = 7 - col;
-        let color_map_idx =
-            ((right_line >> pos_offset & 1) << 1) as u8 | (left_line >> pos_offset & 1) as u8;
-        let color_idx = lookup_color_idx(pallette_address, color_map_idx, cpu);
-        let x_idx = (x + col) % 256;
-        let buffer_idx = buffer_start + x_idx;
-        buffer[buffer_idx] = color_idx;
+        let shift = 7 - col;
+        let color = ((high >> shift & 1) << 1) | (low >> shift & 1);
+        ppu.fifo.background.push_back(cond!(visible, color, 0));
     }
+    ppu.fifo.fetcher.tile_x = ppu.fifo.fetcher.tile_x.wrapping_add(1);
+    ppu.fifo.fetcher.step = FetchStep::Tile;
 }
 
-pub fn write_sprite_tile_line(
-    ly: usize,
-    tile_num: usize,
-    x: isize,
-    y: isize,
-    pallette_address: usize,
-    h_flip: bool,
-    v_flip: bool,
-    buffer: &mut [u8; 256 * 256],
-    cpu: &mut Cpu,
-) {
-    use std::cmp;
-    let tile_map_start = 0x8000;
-    let start_col = cmp::min(8, cmp::max(0, -x)) as usize;
-    let end_col = cmp::max(0, cmp::min(8, 255 - x)) as usize;
-
-    let address_start = tile_map_start + 16 * tile_num;
-    let row = (ly as isize - y) as usize;
-    let normalized_row = cond!(v_flip, 7 - row, row);
-    let left_line = read_address(address_start + normalized_row * 2, cpu) as u16;
-    let right_line = read_address(address_start + normalized_row * 2 + 1, cpu) as u16;
-
-    for col in start_col..end_col {
+// Row of the tile map and of the tile the fetcher is on
+fn fetch_row(ppu: &Ppu) -> usize {
+    if ppu.fifo.in_window {
+        ppu.window_line as usize
+    } else {
+        ppu.ly.wrapping_add(ppu.scy) as usize
+    }
+}
+
+fn fetch_tile_num(ppu: &Ppu) -> u8 {
+    let (map, column) = if ppu.fifo.in_window {
+        let map = cond!(LCDC::WindowTileMap.is_set(ppu), 0x9C00, 0x9800);
+        (map, ppu.fifo.fetcher.tile_x as usize)
+    } else {
+        let map = cond!(LCDC::BGTileMap.is_set(ppu), 0x9C00, 0x9800);
+        (map, (ppu.scx / 8) as usize + ppu.fifo.fetcher.tile_x as usize)
+    };
+    let address = map + 32 * (fetch_row(ppu) / 8 % 32) + column % 32;
+    ppu.vram[address - 0x8000]
+}
+
+fn fetch_tile_data(offset: usize, ppu: &Ppu) -> u8 {
+    let tile_num = ppu.fifo.fetcher.tile_num;
+    let tile_start = if LCDC::Tileset.is_set(ppu) {
+        0x8000 + 16 * tile_num as usize
+    } else {
+        (0x9000 + 16 * (tile_num as i8) as isize) as usize
+    };
+    let address = tile_start + 2 * (fetch_row(ppu) % 8) + offset;
+    ppu.vram[address - 0x8000]
+}
+
+// Mixes the sprite's row into the sprite fifo. Opaque pixels already there
+// from an earlier sprite win over this one's.
+fn fetch_sprite(index: usize, ppu: &mut Ppu) {
+    let address = index * 4;
+    let (y, x, tile_num, flags) = (
+        ppu.oam[address],
+        ppu.oam[address + 1],
+        ppu.oam[address + 2],
+        ppu.oam[address + 3],
+    );
+    let height = sprite_height(ppu);
+    let tile_num = cond!(height == 16, tile_num & 0xFE, tile_num) as usize;
+    let palette = cond!(flags & 0x10 == 0, 0xFF48, 0xFF49);
+    let h_flip = flags & 0x20 != 0;
+    let v_flip = flags & 0x40 != 0;
+    let behind_background = flags & 0x80 != 0;
+
+    let row = (ppu.ly + 16).wrapping_sub(y) % height;
+    let row = cond!(v_flip, height - 1 - row, row) as usize;
+    let tile_address = 16 * tile_num + 2 * row;
+    let low = ppu.vram[tile_address];
+    let high = ppu.vram[tile_address + 1];
+
+    // Columns of a sprite hanging off the left edge are cut off
+    let skip = (ppu.fifo.lx + 8).saturating_sub(x).min(8);
+    while ppu.fifo.sprites.len() < 8 {
+        ppu.fifo.sprites.push_back(TRANSPARENT);
+    }
+    for col in skip..8 {
         let shift = cond!(h_flip, col, 7 - col);
-        let o_palette_idx = ((right_line >> shift & 1) << 1) as u8 + (left_line >> shift & 1) as u8;
-        let color_idx = lookup_color_idx(pallette_address, o_palette_idx, cpu);
-        if o_palette_idx != 0 {
-            buffer[(ly as usize) * 256 + (x + col as isize) as usize] = color_idx;
+        let color = ((high >> shift & 1) << 1) | (low >> shift & 1);
+        let slot = &mut ppu.fifo.sprites[(col - skip) as usize];
+        if slot.color == 0 {
+            *slot = ObjPixel {
+                color: color,
+                palette: palette,
+                behind_background: behind_background,
+            };
         }
     }
 }
 
-pub fn write_window_tile_line(
+pub fn render_scanline(
     ly: u8,
-    tile_num: usize,
-    x: isize,
-    y: isize,
-    tile_map_start: usize,
-    buffer: &mut [u8; 256 * 256],
-    cpu: &mut Cpu,
+    screen_buffer: &mut [u8; 256 * 256],
+    canvas: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ppu: &Ppu,
 ) {
-    use std::cmp;
-    let start_col = cmp::min(8, cmp::max(0, -x)) as usize;
-    let end_col = cmp::max(0, cmp::min(8, 255 - x)) as usize;
-    let address_start = tile_map_start + 16 * tile_num;
-
-    if y > 255 || x > 255 {
+    if ly > 143 {
         return;
     }
-    let row = ly as usize - y as usize;
-
-    let left_line = read_address(address_start + row * 2, cpu);
-    let right_line = read_address(address_start + row * 2 + 1, cpu);
-
-    for col in start_col..end_col {
-        let color_idx = lookup_color_idx(
-            0xFF47,
-            ((right_line >> (7 - col) & 1) << 1) as u8 + (left_line >> (7 - col) & 1) as u8,
-            cpu,
-        );
-        buffer[(ly as usize) * 256 + (x + col as isize) as usize] = color_idx;
+    if LCDC::Power.is_set(ppu) {
+        let start = 256 * ly as usize;
+        screen_buffer[start..start + 160].copy_from_slice(&ppu.line_buffer);
+        buffer_line_to_image_buffer(ly, canvas, screen_buffer)
+    } else {
+        clear_buffer(screen_buffer);
+        buffer_line_to_image_buffer(ly, canvas, screen_buffer)
+    }
+}
+
+fn clear_buffer(screen_buffer: &mut [u8; 256 * 256]) {
+    for i in 0..(256 * 256) {
+        screen_buffer[i] = 0;
     }
 }
 
-pub fn lookup_color_idx(address: usize, pallete_idx: u8, cpu: &mut Cpu) -> u8 {
-    (read_address(address, cpu) >> (pallete_idx * 2)) & 0b11
+pub fn lookup_color_idx(address: usize, pallete_idx: u8, ppu: &Ppu) -> u8 {
+    (ppu.read(address) >> (pallete_idx * 2)) & 0b11
 }
 
 pub fn buffer_to_image_buffer(
@@ -1,4 +1,4 @@
-use cpu::*;
+use sm83::CpuBus;
 use utility::*;
 
 #[derive(Debug, Copy, Clone)]
@@ -28,83 +28,92 @@ pub enum Register {
     PC, // Program Counter
 }
 
-pub fn read_register(reg: Register, cpu: &mut Cpu) -> u8 {
+// The memory address a register refers to, e.g. (HL) or ($FF00+C)
+fn indirect_address<S: CpuBus>(reg: Register, gb: &mut S) -> usize {
     use self::Register::*;
     match reg {
-        A => cpu.a,
-        B => cpu.b,
-        C => cpu.c,
-        CH => safe_read_address(0xFF00 + read_register(C, cpu) as usize, cpu),
-        D => cpu.d,
-        E => cpu.e,
-        F => cpu.f,
-        H => cpu.h,
-        L => cpu.l,
-        HL_ADDR => safe_read_address(read_multi_register(HL, cpu) as usize, cpu),
-        BC_ADDR => safe_read_address(read_multi_register(BC, cpu) as usize, cpu),
-        DE_ADDR => safe_read_address(read_multi_register(DE, cpu) as usize, cpu),
-        ADDR(addr) => safe_read_address(addr as usize, cpu),
-        SP_OFF(offset) => safe_read_address(wrapping_off_u16_i8(cpu.sp, offset) as usize, cpu),
-        _ => {
-            println!("Failed to read {:?}", reg);
-            unreachable!()
+        CH => 0xFF00 + gb.cpu().c as usize,
+        HL_ADDR => read_multi_register(HL, gb) as usize,
+        BC_ADDR => read_multi_register(BC, gb) as usize,
+        DE_ADDR => read_multi_register(DE, gb) as usize,
+        ADDR(addr) => addr as usize,
+        SP_OFF(offset) => wrapping_off_u16_i8(gb.cpu().sp, offset) as usize,
+        _ => unreachable!("{:?} doesn't refer to memory", reg),
+    }
+}
+
+pub fn read_register<S: CpuBus>(reg: Register, gb: &mut S) -> u8 {
+    use self::Register::*;
+    match reg {
+        A => gb.cpu().a,
+        B => gb.cpu().b,
+        C => gb.cpu().c,
+        D => gb.cpu().d,
+        E => gb.cpu().e,
+        F => gb.cpu().f,
+        H => gb.cpu().h,
+        L => gb.cpu().l,
+        CH | HL_ADDR | BC_ADDR | DE_ADDR | ADDR(_) | SP_OFF(_) => {
+            let address = indirect_address(reg, gb);
+            gb.read(address)
         }
+        // The decoder only pairs 8 bit operations with 8 bit operands
+        _ => unreachable!("{:?} isn't an 8 bit register", reg),
     }
 }
 
-pub fn read_multi_register(reg: Register, cpu: &mut Cpu) -> u16 {
+pub fn read_multi_register<S: CpuBus>(reg: Register, gb: &mut S) -> u16 {
     use self::Register::*;
     match reg {
-        HL => ((cpu.h as u16) << 8) + (cpu.l as u16),
-        AF => ((cpu.a as u16) << 8) + (cpu.f as u16),
-        BC => ((cpu.b as u16) << 8) + (cpu.c as u16),
-        DE => ((cpu.d as u16) << 8) + (cpu.e as u16),
-        SP => cpu.sp,
-        _ => unreachable!(),
+        HL => ((gb.cpu().h as u16) << 8) + (gb.cpu().l as u16),
+        AF => ((gb.cpu().a as u16) << 8) + (gb.cpu().f as u16),
+        BC => ((gb.cpu().b as u16) << 8) + (gb.cpu().c as u16),
+        DE => ((gb.cpu().d as u16) << 8) + (gb.cpu().e as u16),
+        SP => gb.cpu().sp,
+        _ => unreachable!("{:?} isn't a 16 bit register", reg),
     }
 }
 
-pub fn write_register(reg: Register, val: u8, cpu: &mut Cpu) -> () {
+pub fn write_register<S: CpuBus>(reg: Register, val: u8, gb: &mut S) -> () {
     use self::Register::*;
     match reg {
-        A => cpu.a = val,
-        B => cpu.b = val,
-        C => cpu.c = val,
-        D => cpu.d = val,
-        E => cpu.e = val,
-        F => cpu.f = val & 0xF0,
-        H => cpu.h = val,
-        L => cpu.l = val,
-        CH => safe_write_address(0xFF00 + read_register(C, cpu) as usize, val, cpu),
-        HL_ADDR => safe_write_address(read_multi_register(HL, cpu) as usize, val, cpu),
-        BC_ADDR => safe_write_address(read_multi_register(BC, cpu) as usize, val, cpu),
-        DE_ADDR => safe_write_address(read_multi_register(DE, cpu) as usize, val, cpu),
-        ADDR(address) => safe_write_address(address as usize, val, cpu),
-        _ => unreachable!(),
+        A => gb.cpu().a = val,
+        B => gb.cpu().b = val,
+        C => gb.cpu().c = val,
+        D => gb.cpu().d = val,
+        E => gb.cpu().e = val,
+        F => gb.cpu().f = val & 0xF0,
+        H => gb.cpu().h = val,
+        L => gb.cpu().l = val,
+        CH | HL_ADDR | BC_ADDR | DE_ADDR | ADDR(_) => {
+            let address = indirect_address(reg, gb);
+            gb.write(address, val)
+        }
+        _ => unreachable!("{:?} isn't an 8 bit register", reg),
     }
 }
 
-pub fn write_multi_register(reg: Register, val: u16, cpu: &mut Cpu) -> () {
+pub fn write_multi_register<S: CpuBus>(reg: Register, val: u16, gb: &mut S) -> () {
     use self::Register::*;
     let (l_byte, r_byte) = ((val >> 8) as u8, (0x00FF & val) as u8);
     match reg {
         HL => {
-            cpu.h = l_byte;
-            cpu.l = r_byte;
+            gb.cpu().h = l_byte;
+            gb.cpu().l = r_byte;
         }
         AF => {
-            cpu.a = l_byte;
-            cpu.f = r_byte & 0xF0;
+            gb.cpu().a = l_byte;
+            gb.cpu().f = r_byte & 0xF0;
         }
         BC => {
-            cpu.b = l_byte;
-            cpu.c = r_byte;
+            gb.cpu().b = l_byte;
+            gb.cpu().c = r_byte;
         }
         DE => {
-            cpu.d = l_byte;
-            cpu.e = r_byte;
+            gb.cpu().d = l_byte;
+            gb.cpu().e = r_byte;
         }
-        SP => cpu.sp = val,
-        _ => unreachable!(),
+        SP => gb.cpu().sp = val,
+        _ => unreachable!("{:?} isn't a 16 bit register", reg),
     };
 }
\ No newline at end of file
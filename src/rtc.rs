@@ -0,0 +1,195 @@
+use gameboy::CPU_HZ;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Size of the clock state stored after the ram in save files: the current and
+// latched registers as 32 bit words followed by a 64 bit unix timestamp
+pub const RTC_SAVE_SIZE: usize = 48;
+
+const DH_DAY_HI: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub day_lo: u8,
+    // bit 0: day counter bit 8, bit 6: halt, bit 7: day counter carry
+    pub day_hi: u8,
+}
+
+impl RtcRegisters {
+    fn days(&self) -> u64 {
+        (((self.day_hi & DH_DAY_HI) as u64) << 8) | self.day_lo as u64
+    }
+
+    fn set_days(&mut self, days: u64) {
+        self.day_lo = days as u8;
+        self.day_hi = (self.day_hi & !DH_DAY_HI) | ((days >> 8) as u8 & DH_DAY_HI);
+    }
+
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    // Counters only carry on the exact overflow value, anything written out
+    // of range counts up to the register's bit width and wraps to 0 silently
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let days = self.days() + days;
+        if days >= 512 {
+            self.day_hi |= DH_CARRY;
+        }
+        self.set_days(days % 512);
+    }
+
+    fn advance_seconds(&mut self, mut secs: u64) {
+        while secs > 0 && !self.in_range() {
+            self.tick_second();
+            secs -= 1;
+        }
+        if secs == 0 {
+            return;
+        }
+        let total = self.seconds as u64 + 60 * (self.minutes as u64 + 60 * self.hours as u64) + secs;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.add_days(total / 86400);
+    }
+
+    fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.day_lo,
+            0x0C => self.day_hi | 0x3E,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, reg: usize, val: u8) {
+        match reg {
+            0x08 => self.seconds = val & 0x3F,
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.day_lo = val,
+            0x0C => self.day_hi = val & (DH_DAY_HI | DH_HALT | DH_CARRY),
+            _ => {}
+        }
+    }
+}
+
+// MBC3 real time clock. The clock counts in `regs`, reads see the copy made
+// by the last latch (writing 0x00 then 0x01 to 0x6000-0x7FFF).
+#[derive(Default)]
+pub struct Rtc {
+    pub regs: RtcRegisters,
+    pub latched: RtcRegisters,
+    latch_val: u8,
+    cycles: usize,
+}
+
+impl Rtc {
+    pub fn halted(&self) -> bool {
+        self.regs.day_hi & DH_HALT != 0
+    }
+
+    pub fn read(&self, reg: usize) -> u8 {
+        self.latched.read(reg)
+    }
+
+    pub fn write(&mut self, reg: usize, val: u8) {
+        // Writing the seconds resets the sub second divider
+        if reg == 0x08 {
+            self.cycles = 0;
+        }
+        self.regs.write(reg, val);
+        self.latched.write(reg, val);
+    }
+
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch_val == 0x00 && val == 0x01 {
+            self.latched = self.regs;
+        }
+        self.latch_val = val;
+    }
+
+    // Advances the clock by emulated cpu clocks
+    pub fn tick(&mut self, cycles: usize) {
+        if self.halted() {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CPU_HZ {
+            self.cycles -= CPU_HZ;
+            self.regs.tick_second();
+        }
+    }
+
+    // Advances the clock by real time, e.g. the time spent switched off
+    pub fn advance_seconds(&mut self, secs: u64) {
+        if !self.halted() {
+            self.regs.advance_seconds(secs);
+        }
+    }
+
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for regs in &[self.regs, self.latched] {
+            for &val in &[regs.seconds, regs.minutes, regs.hours, regs.day_lo, regs.day_hi] {
+                data.extend_from_slice(&(val as u32).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    // Restores the clock and catches up with the time passed since it was saved
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        let word = |i: usize| data[i * 4];
+        let regs = |i: usize| RtcRegisters {
+            seconds: word(i),
+            minutes: word(i + 1),
+            hours: word(i + 2),
+            day_lo: word(i + 3),
+            day_hi: word(i + 4),
+        };
+        self.regs = regs(0);
+        self.latched = regs(5);
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        let saved_at = u64::from_le_bytes(timestamp);
+        self.advance_seconds(unix_time().saturating_sub(saved_at));
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,108 @@
+// Something a component needs to happen at a known clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    // The ppu leaves mode 2 for mode 3
+    OamScanEnd,
+    // The ppu may be done with mode 3, fires again until the pixel fifo has
+    // shifted out the whole line
+    TransferEnd,
+    // LY moves on to the next line
+    LineEnd,
+    // LY goes back to 0 early in line 153
+    LyReset,
+    // TIMA overflowed and TMA gets loaded into it
+    TimerOverflow,
+    // A bit got shifted out of SB
+    SerialBit,
+    // OAM DMA is done and the bus is released
+    DmaEnd,
+    // 512hz clock of the apu length counters, envelopes and sweep
+    FrameSequencer,
+}
+
+// The system clock, along with the pending events and the clock they fire at.
+// Every event is pending at most once and there are only a handful of them,
+// so a flat list is all it takes.
+pub struct Scheduler {
+    // Clocks run since power on
+    now: u64,
+    events: Vec<(u64, Event)>,
+    // Timestamp of the earliest event, checked on every tick
+    next: u64,
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler {
+            now: 0,
+            events: Vec::with_capacity(8),
+            next: u64::MAX,
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    // Schedules `event` at `timestamp`, replacing it if it was already pending
+    pub fn schedule(&mut self, timestamp: u64, event: Event) {
+        self.events.retain(|&(_, e)| e != event);
+        self.events.push((timestamp, event));
+        self.update_next();
+    }
+
+    pub fn schedule_in(&mut self, cycles: u64, event: Event) {
+        let timestamp = self.now + cycles;
+        self.schedule(timestamp, event);
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|&(_, e)| e != event);
+        self.update_next();
+    }
+
+    pub fn is_scheduled(&self, event: Event) -> bool {
+        self.events.iter().any(|&(_, e)| e == event)
+    }
+
+    // Timestamp of the nearest event
+    pub fn next_event(&self) -> Option<u64> {
+        cond!(self.events.is_empty(), None, Some(self.next))
+    }
+
+    // Clocks until the nearest event
+    pub fn cycles_until_next(&self) -> Option<u64> {
+        self.next_event().map(|timestamp| timestamp.saturating_sub(self.now))
+    }
+
+    // Takes the earliest event that is due by now
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        if self.now < self.next {
+            return None;
+        }
+        let index = self
+            .events
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &(timestamp, _))| timestamp)
+            .map(|(i, _)| i)?;
+        let due = self.events.swap_remove(index);
+        self.update_next();
+        Some(due)
+    }
+
+    fn update_next(&mut self) {
+        self.next = self
+            .events
+            .iter()
+            .map(|&(timestamp, _)| timestamp)
+            .min()
+            .unwrap_or(u64::MAX);
+    }
+}
@@ -0,0 +1,48 @@
+// Serial transfers on the internal clock shift at 8192hz
+pub const SERIAL_BIT_CYCLES: u64 = 512;
+
+// The link port. Nothing is ever plugged in, so transfers shift in ones.
+#[derive(Default)]
+pub struct Serial {
+    pub sb: u8,
+    pub sc: u8,
+    // Bits of SB still to be shifted out by the running transfer
+    bits_left: u8,
+}
+
+impl Serial {
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF01 => self.sb,
+            0xFF02 => self.sc,
+            _ => 0xFF,
+        }
+    }
+
+    // Returns true if the write started a transfer on the internal clock
+    pub fn write(&mut self, address: usize, val: u8) -> bool {
+        match address {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.sc = val;
+                if val & 0x81 == 0x81 {
+                    self.bits_left = 8;
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    // Returns true once the last bit is out and the interrupt is due
+    pub fn shift_bit(&mut self) -> bool {
+        self.sb = (self.sb << 1) | 1;
+        self.bits_left -= 1;
+        if self.bits_left > 0 {
+            return false;
+        }
+        self.sc &= 0x7F;
+        true
+    }
+}
@@ -0,0 +1,101 @@
+use bus::Bus;
+
+// Where the cpu hit an illegal opcode and locked up. `bank` is the rom bank
+// for addresses in the cartridge rom.
+#[derive(Debug, Clone, Copy)]
+pub struct Lockup {
+    pub address: usize,
+    pub opcode: u8,
+    pub bank: Option<usize>,
+}
+
+// The cpu core's own state: its registers and what it's doing. Everything it
+// reaches over the bus belongs to the GameBoy.
+pub struct Sm83 {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub interrupt_master_enabled: bool,
+    pub ime_scheduled: bool,
+    pub halted: bool,
+    pub halt_bug: bool,
+    pub stopped: bool,
+    pub lockup: Option<Lockup>,
+}
+
+impl Default for Sm83 {
+    fn default() -> Sm83 {
+        Sm83 {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: 0,
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+            interrupt_master_enabled: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            lockup: None,
+        }
+    }
+}
+
+impl Sm83 {
+    // Registers as the DMG boot rom leaves them
+    pub fn init_post_boot_registers(&mut self) {
+        self.a = 0x01;
+        self.f = 0xB0;
+        self.b = 0x00;
+        self.c = 0x13;
+        self.d = 0x00;
+        self.e = 0xD8;
+        self.h = 0x01;
+        self.l = 0x4D;
+        self.sp = 0xFFFE;
+    }
+}
+
+// Everything the cpu core reaches for: the bus, its own registers and the
+// few lines into the rest of the system instructions use. Instructions and
+// interrupts only go through this, so they run the same on a GameBoy as on a
+// mock bus in a test.
+pub trait CpuBus: Bus {
+    fn cpu(&mut self) -> &mut Sm83;
+
+    // Reads without taking any time, for decoding instructions
+    fn peek(&mut self, address: usize) -> u8;
+
+    // Lets the rest of the system run for the cpu's internal cycles
+    fn tick(&mut self, _cycles: usize) {}
+
+    fn interrupt_flag(&self) -> u8;
+    fn set_interrupt_flag(&mut self, val: u8);
+    fn interrupt_enable(&self) -> u8;
+    fn set_interrupt_enable(&mut self, val: u8);
+
+    // Whether a button on a selected joypad line is held, which ends STOP
+    fn joypad_line_low(&self) -> bool {
+        false
+    }
+
+    // STOP resets DIV
+    fn reset_div(&mut self) {}
+
+    // Rom bank mapped at `address`, if it's in the cartridge rom
+    fn rom_bank_at(&self, _address: usize) -> Option<usize> {
+        None
+    }
+}
@@ -0,0 +1,144 @@
+// DIV and TIMA are both driven by a 16 bit counter running at the cpu clock.
+// DIV is its upper byte, TIMA counts the falling edges of the counter bit
+// selected by TAC (ANDed with the enable bit), so writes to DIV or TAC that
+// make that signal fall also count.
+// Nothing ticks the timer, it catches up with the cpu clock whenever it is
+// accessed or its next overflow comes due.
+#[derive(Default)]
+pub struct Timer {
+    pub counter: u16,
+    // Cpu clock the counter was last brought up to
+    pub synced_at: u64,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    // TIMA overflowed during the last M-cycle, it reads 0 until TMA is loaded
+    // at the end of the next one
+    overflow: bool,
+    // TMA was loaded into TIMA during the last M-cycle
+    reloaded: bool,
+}
+
+impl Timer {
+    // Runs the counter forward to the cpu clock `now`, returns true if the
+    // timer interrupt should be requested. Stretches without a falling edge
+    // are skipped over in one go. The counter moves in whole M-cycles, clocks
+    // short of one are left for the next sync.
+    pub fn sync(&mut self, now: u64) -> bool {
+        let mut cycles = now.saturating_sub(self.synced_at);
+        let mut interrupt = false;
+        while cycles >= 4 {
+            if !self.overflow && !self.reloaded {
+                let until_edge = self.cycles_until_edge().unwrap_or(cycles).min(cycles);
+                let skip = until_edge.saturating_sub(4) & !3;
+                self.counter = self.counter.wrapping_add(skip as u16);
+                cycles -= skip;
+            }
+            interrupt |= self.step();
+            cycles -= 4;
+        }
+        self.synced_at = now.saturating_sub(cycles);
+        interrupt
+    }
+
+    // Cpu clock at which the next overflow requests an interrupt, as of the
+    // last sync. TMA is loaded an M-cycle after the overflowing edge.
+    pub fn next_interrupt(&self) -> Option<u64> {
+        if self.overflow {
+            return Some(self.synced_at + 4);
+        }
+        let period = 2 * self.bit() as u64;
+        self.cycles_until_edge()
+            .map(|until_edge| self.synced_at + until_edge + (0xFF - self.tima as u64) * period + 4)
+    }
+
+    // One M-cycle
+    fn step(&mut self) -> bool {
+        let mut interrupt = false;
+        self.reloaded = false;
+        if self.overflow {
+            self.overflow = false;
+            self.tima = self.tma;
+            self.reloaded = true;
+            interrupt = true;
+        }
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.count_falling_edge(signal);
+        interrupt
+    }
+
+    // Clocks until the selected counter bit next falls, None while disabled
+    fn cycles_until_edge(&self) -> Option<u64> {
+        if self.tac & 0b100 == 0 {
+            return None;
+        }
+        let period = 2 * self.bit() as u64;
+        Some(period - self.counter as u64 % period)
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, val: u8) {
+        match address {
+            0xFF04 => self.reset_div(),
+            0xFF05 => {
+                // Writing during the reload cycle is ignored, writing while
+                // TIMA reads 0 cancels the reload and the interrupt
+                if !self.reloaded {
+                    self.overflow = false;
+                    self.tima = val;
+                }
+            }
+            0xFF06 => {
+                self.tma = val;
+                if self.reloaded {
+                    self.tima = val;
+                }
+            }
+            0xFF07 => {
+                let signal = self.signal();
+                self.tac = val & 0b111;
+                self.count_falling_edge(signal);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn reset_div(&mut self) {
+        let signal = self.signal();
+        self.counter = 0;
+        self.count_falling_edge(signal);
+    }
+
+    fn bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0 => 1 << 9, // 4096hz
+            1 => 1 << 3, // 262144hz
+            2 => 1 << 5, // 65536hz
+            _ => 1 << 7, // 16384hz
+        }
+    }
+
+    fn signal(&self) -> bool {
+        self.tac & 0b100 != 0 && self.counter & self.bit() != 0
+    }
+
+    fn count_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            if overflow {
+                self.overflow = true;
+            }
+        }
+    }
+}